@@ -0,0 +1,82 @@
+//! Allocations and time spent storing request headers, against the
+//! lowercased `HashMap<String, String>` headers used to be kept in.
+//!
+//! Run with: cargo bench --features alloc-tracking --bench headers
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rsttp::alloc_tracking::{AllocStats, CountingAllocator, measure};
+use rsttp::http::{Headers, Request};
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: u32 = 100_000;
+
+const FIELDS: [(&str, &str); 8] = [
+    ("Host", "example.com"),
+    ("User-Agent", "Mozilla/5.0 (X11; Linux x86_64)"),
+    ("Accept", "text/html,application/xhtml+xml"),
+    ("Accept-Encoding", "gzip, deflate"),
+    ("Accept-Language", "en-GB,en;q=0.9"),
+    ("Connection", "keep-alive"),
+    ("Cache-Control", "no-cache"),
+    ("X-Request-Id", "3f2a9c"),
+];
+
+fn headers() -> Headers {
+    let mut headers: Headers = Headers::with_capacity(FIELDS.len());
+    for (name, val) in FIELDS {
+        headers.append(name, String::from(val));
+    }
+
+    headers
+}
+
+fn lowercased_map() -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, val) in FIELDS {
+        headers.insert(name.to_lowercase(), String::from(val));
+    }
+
+    headers
+}
+
+fn bench<T>(name: &str, f: impl Fn() -> T) {
+    let (_, stats): (T, AllocStats) = measure(&f);
+
+    let started: Instant = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    let per_iter: Duration = started.elapsed() / ITERATIONS;
+
+    println!(
+        "{:<28} {:>4} allocations {:>6} bytes {:>8.0?}/iter",
+        name, stats.allocations, stats.bytes, per_iter
+    );
+}
+
+fn main() {
+    let mut raw: String = String::from("GET /index.html HTTP/1.1\r\n");
+    for (name, val) in FIELDS {
+        raw.push_str(&format!("{}: {}\r\n", name, val));
+    }
+    raw.push_str("\r\n");
+
+    bench("Headers (insert)", headers);
+    bench("HashMap, lowercased (insert)", lowercased_map);
+
+    let stored: Headers = headers();
+    let map: HashMap<String, String> = lowercased_map();
+    bench("Headers (lookup)", || {
+        stored.get("accept-encoding").cloned()
+    });
+    bench("HashMap, lowercased (lookup)", || {
+        map.get(&"Accept-Encoding".to_lowercase()).cloned()
+    });
+
+    bench("Request::new", || Request::new(raw.as_bytes()).unwrap());
+}
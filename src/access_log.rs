@@ -0,0 +1,249 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::http::Request;
+use crate::http::percent::percent_decode;
+
+const REDACTED: &str = "[REDACTED]";
+
+pub type AccessLogSink = Box<dyn Fn(&AccessLogEntry) + Send + Sync + 'static>;
+
+pub(crate) type FlushHook = Arc<dyn Fn() + Send + Sync + 'static>;
+
+/// One served request, as handed to the access log sink. Redaction has
+/// already been applied to `query` and `headers`.
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub client: Option<SocketAddr>,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub status: u16,
+    pub duration: Duration,
+}
+
+/// Records served requests, keeping a configurable share of them per status
+/// range and masking sensitive headers and query parameters first.
+///
+/// Requests are logged at rate 1.0 unless a sampling rule covering their
+/// status says otherwise; the first matching rule wins. `Authorization`,
+/// `Proxy-Authorization` and `Cookie` are redacted by default.
+pub struct AccessLog {
+    rules: Vec<SampleRule>,
+    redacted_headers: Vec<String>,
+    redacted_query_params: Vec<String>,
+    sink: AccessLogSink,
+    flush: Option<FlushHook>,
+}
+
+struct SampleRule {
+    statuses: RangeInclusive<u16>,
+    rate: f64,
+    seen: AtomicU64,
+}
+
+impl AccessLog {
+    pub fn new() -> AccessLog {
+        AccessLog {
+            rules: vec![],
+            redacted_headers: ["authorization", "proxy-authorization", "cookie"]
+                .map(String::from)
+                .to_vec(),
+            redacted_query_params: vec![],
+            sink: Box::new(|entry: &AccessLogEntry| {
+                info!(
+                    target: "rsttp::access",
+                    client = ?entry.client,
+                    method = entry.method,
+                    path = entry.path,
+                    query = entry.query,
+                    status = entry.status,
+                    duration_us = entry.duration.as_micros() as u64,
+                    headers = ?entry.headers,
+                );
+            }),
+            flush: None,
+        }
+    }
+
+    /// Logs a `rate` share (0.0 to 1.0) of responses whose status falls in
+    /// `statuses`, e.g. `sample(200..=299, 0.01)` for 1% of successes.
+    pub fn sample(&mut self, statuses: RangeInclusive<u16>, rate: f64) -> &mut Self {
+        self.rules.push(SampleRule {
+            statuses,
+            rate: rate.clamp(0.0, 1.0),
+            seen: AtomicU64::new(0),
+        });
+        self
+    }
+
+    pub fn redact_header(&mut self, name: &str) -> &mut Self {
+        self.redacted_headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    pub fn redact_query_param(&mut self, name: &str) -> &mut Self {
+        self.redacted_query_params.push(String::from(name));
+        self
+    }
+
+    /// Replaces the default sink, which emits a `tracing` event with target
+    /// `rsttp::access`.
+    pub fn sink(&mut self, sink: impl Fn(&AccessLogEntry) + Send + Sync + 'static) -> &mut Self {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    /// Runs `flush` when the server stops, after its last entries have been
+    /// passed to the sink, so a sink that buffers can write them out.
+    pub fn on_flush(&mut self, flush: impl Fn() + Send + Sync + 'static) -> &mut Self {
+        self.flush = Some(Arc::new(flush));
+        self
+    }
+
+    pub(crate) fn flush_hook(&self) -> Option<FlushHook> {
+        self.flush.clone()
+    }
+
+    /// Captures the redacted parts of `req` needed for its entry, before the
+    /// request is handed to the router.
+    pub(crate) fn begin(&self, req: &Request, client: Option<SocketAddr>) -> AccessLogEntry {
+        AccessLogEntry {
+            client,
+            method: req.req_type.to_string(),
+            path: req.path.encoded(),
+            query: req.query.as_deref().map(|query| self.redact_query(query)),
+            headers: req
+                .headers
+                .iter()
+                .map(|(name, val)| {
+                    let redacted: bool = self
+                        .redacted_headers
+                        .iter()
+                        .any(|h| name.as_str().eq_ignore_ascii_case(h));
+
+                    (
+                        name.to_string(),
+                        match redacted {
+                            true => String::from(REDACTED),
+                            false => val.clone(),
+                        },
+                    )
+                })
+                .collect(),
+            status: 0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// Completes `entry` and passes it to the sink if it is sampled.
+    pub(crate) fn finish(&self, mut entry: AccessLogEntry, status: u16, duration: Duration) {
+        entry.status = status;
+        entry.duration = duration;
+
+        if self.is_sampled(status) {
+            (self.sink)(&entry);
+        }
+    }
+
+    /// Spreads sampled entries evenly: the nth response matching a rule is
+    /// kept whenever `n * rate` crosses a whole number.
+    fn is_sampled(&self, status: u16) -> bool {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.statuses.contains(&status))
+        else {
+            return true;
+        };
+
+        let n: u64 = rule.seen.fetch_add(1, Ordering::Relaxed);
+
+        ((n + 1) as f64 * rule.rate).floor() > (n as f64 * rule.rate).floor()
+    }
+
+    /// Keys are compared decoded, as handlers read them, so `%74oken` is
+    /// redacted along with `token`. Pairs whose key doesn't decode are
+    /// redacted too.
+    fn redact_query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| {
+                let key: &str = pair.split_once('=').map_or(pair, |(key, _)| key);
+                let redacted: bool = match percent_decode(&key.replace('+', " ")) {
+                    Ok(decoded) => self.redacted_query_params.contains(&decoded),
+                    Err(_) => true,
+                };
+
+                if redacted {
+                    format!("{}={}", key, REDACTED)
+                } else {
+                    String::from(pair)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<(&RangeInclusive<u16>, f64)> = self
+            .rules
+            .iter()
+            .map(|rule| (&rule.statuses, rule.rate))
+            .collect();
+
+        f.debug_struct("AccessLog")
+            .field("rules", &rules)
+            .field("redacted_headers", &self.redacted_headers)
+            .field("redacted_query_params", &self.redacted_query_params)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(query: &str) -> String {
+        let mut log: AccessLog = AccessLog::new();
+        log.redact_query_param("token");
+
+        log.redact_query(query)
+    }
+
+    #[test]
+    fn redacts_named_param() {
+        assert_eq!(redact("a=1&token=secret"), "a=1&token=[REDACTED]");
+    }
+
+    #[test]
+    fn redacts_percent_encoded_key() {
+        assert_eq!(redact("to%6Ben=secret"), "to%6Ben=[REDACTED]");
+        assert_eq!(redact("%74oken=secret&b=2"), "%74oken=[REDACTED]&b=2");
+    }
+
+    #[test]
+    fn redacts_undecodable_key() {
+        assert_eq!(redact("tok%zzen=secret"), "tok%zzen=[REDACTED]");
+    }
+
+    #[test]
+    fn keeps_other_params() {
+        assert_eq!(redact("tokens=1&my_token=2"), "tokens=1&my_token=2");
+    }
+}
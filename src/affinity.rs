@@ -0,0 +1,64 @@
+use std::io;
+
+/// Whether [`pin_current_thread`] can succeed on this platform.
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Restricts the calling thread to run only on the given CPU core.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> io::Result<()> {
+    check_core(core)?;
+
+    // SAFETY: an all-zeroes cpu_set_t is the empty set.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+
+    // SAFETY: CPU_SET indexes a fixed array without checking, and
+    // check_core has ruled out cores at or past CPU_SETSIZE.
+    unsafe { libc::CPU_SET(core, &mut set) };
+
+    // SAFETY: `set` is a valid cpu_set_t and pid 0 targets the calling thread.
+    let res = unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(core: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "thread affinity is not supported on this platform (core {})",
+            core
+        ),
+    ))
+}
+
+/// Fails for a core a `cpu_set_t` can't hold or that isn't online.
+#[cfg(target_os = "linux")]
+pub fn check_core(core: usize) -> io::Result<()> {
+    let invalid = |reason: String| {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot pin to core {}: {}", core, reason),
+        ))
+    };
+
+    if core >= libc::CPU_SETSIZE as usize {
+        return invalid(format!("cpu sets hold {} cores", libc::CPU_SETSIZE));
+    }
+
+    // SAFETY: sysconf only reads a system setting.
+    let online: libc::c_long = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if online > 0 && core >= online as usize {
+        return invalid(format!("only {} cores are online", online));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_core(_core: usize) -> io::Result<()> {
+    Ok(())
+}
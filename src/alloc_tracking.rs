@@ -0,0 +1,112 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use thiserror::Error;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A global allocator that forwards to [`System`] and counts allocations made
+/// on each thread. Install it in a binary, test or bench with
+/// `#[global_allocator]` to make [`measure`] report real numbers.
+#[derive(Debug)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        // SAFETY: forwarded unchanged to the system allocator.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        // SAFETY: forwarded unchanged to the system allocator.
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        // SAFETY: forwarded unchanged to the system allocator.
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: forwarded unchanged to the system allocator.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+fn record(bytes: usize) {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    let _ = ALLOCATED_BYTES.try_with(|total| total.set(total.get() + bytes));
+}
+
+fn snapshot() -> AllocStats {
+    AllocStats {
+        allocations: ALLOCATIONS.try_with(Cell::get).unwrap_or(0),
+        bytes: ALLOCATED_BYTES.try_with(Cell::get).unwrap_or(0),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AllocStats {
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+/// Runs `f` and returns the allocations it made on the current thread.
+pub fn measure<T, F: FnOnce() -> T>(f: F) -> (T, AllocStats) {
+    let before: AllocStats = snapshot();
+    let res: T = f();
+    let after: AllocStats = snapshot();
+
+    (
+        res,
+        AllocStats {
+            allocations: after.allocations - before.allocations,
+            bytes: after.bytes - before.bytes,
+        },
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AllocBudget {
+    pub max_allocations: usize,
+    pub max_bytes: usize,
+}
+
+impl AllocBudget {
+    pub fn check(&self, stats: &AllocStats) -> Result<(), BudgetExceeded> {
+        if stats.allocations > self.max_allocations || stats.bytes > self.max_bytes {
+            return Err(BudgetExceeded {
+                stats: *stats,
+                budget: *self,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Panics if `stats` goes over the budget; meant for tests and benches.
+    pub fn assert_within(&self, stats: &AllocStats) {
+        if let Err(e) = self.check(stats) {
+            panic!("{}", e);
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "allocation budget exceeded: {} allocations / {} bytes (budget {} / {})",
+    stats.allocations,
+    stats.bytes,
+    budget.max_allocations,
+    budget.max_bytes
+)]
+pub struct BudgetExceeded {
+    pub stats: AllocStats,
+    pub budget: AllocBudget,
+}
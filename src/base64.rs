@@ -0,0 +1,47 @@
+/// Decodes standard (RFC 4648 section 4) base64. Trailing `=` padding is
+/// optional; any other byte outside the alphabet fails the decode.
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    let input: &[u8] = input.as_bytes();
+    let data: &[u8] = match input.iter().position(|b| *b == b'=') {
+        Some(idx) if input[idx..].len() <= 2 && input[idx..].iter().all(|b| *b == b'=') => {
+            if input.len() % 4 != 0 {
+                return None;
+            }
+            &input[..idx]
+        }
+        Some(_) => return None,
+        None => input,
+    };
+
+    if data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+
+    for b in data {
+        acc = (acc << 6) | u32::from(sextet(*b)?);
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    Some(out)
+}
+
+fn sextet(b: u8) -> Option<u8> {
+    match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a' + 26),
+        b'0'..=b'9' => Some(b - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The server's source of time, swappable so tests can move time forward
+/// without sleeping. Socket timeouts always use real time.
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> Instant;
+
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The real clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still until advanced.
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    start_system: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            start: Instant::now(),
+            start_system: SystemTime::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        if let Ok(mut elapsed) = self.elapsed.lock() {
+            *elapsed += by;
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.elapsed
+            .lock()
+            .map_or(Duration::ZERO, |elapsed| *elapsed)
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_system + self.elapsed()
+    }
+}
@@ -1,47 +1,227 @@
-use std::{error::Error, fmt, str::FromStr, time::Duration};
+use std::{error::Error, fmt, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+
+use crate::access_log::AccessLog;
+use crate::affinity;
+use crate::clock::{Clock, SystemClock};
+use crate::experiment::Experiments;
+use crate::flags::FeatureFlags;
+use crate::http::ReqType;
+use crate::quota::Quotas;
+use crate::transfer::TransferAccounting;
+use crate::watchdog::Watchdog;
+
+#[cfg(feature = "alloc-tracking")]
+use crate::alloc_tracking::AllocBudget;
 
 #[derive(Debug)]
 pub struct Config<Ctx: Send + Sync> {
     pub port: i32,
     pub ctx: Ctx,
     pub persist_connection_for: Duration,
+    pub tcp_nodelay: bool,
+    /// Non-standard methods (e.g. `PROPFIND`) the server accepts besides those
+    /// routes are registered for; requests using any other unknown method are
+    /// answered with 501.
+    pub extension_methods: Vec<String>,
+    pub runtime: Runtime,
+    /// Most connections a single client IP may hold open at once; further
+    /// connections are answered with 429 and closed. `None` means unlimited.
+    pub max_connections_per_client: Option<usize>,
+    /// Addresses of reverse proxies in front of the server. Their connections
+    /// carry many clients, so they are exempt from per-client limits, and
+    /// their `Forwarded`/`X-Forwarded-For` headers are used to find the real
+    /// client for `Request::client_addr`.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Largest serialized response the server will send. Larger responses
+    /// are replaced with a 500.
+    pub max_response_size: Option<usize>,
+    /// Longest the server spends writing one response. A client too slow to
+    /// take it in time has its connection closed.
+    pub response_write_timeout: Option<Duration>,
+    /// Largest request head, the request line and header fields, the server
+    /// will buffer. Larger heads are answered with 431.
+    pub max_header_bytes: usize,
+    /// Longest a client may take to send a request head once its first
+    /// byte has arrived. Slower clients are answered with 408.
+    pub header_read_timeout: Option<Duration>,
+    /// Longest request target the server accepts. Longer targets are
+    /// answered with 414. RFC 9112 recommends at least 8000 octets.
+    pub max_target_len: usize,
+    /// Most header fields a request may carry, chunked body trailers
+    /// included, before it is answered with 431.
+    pub max_header_count: usize,
+    /// Largest request body the server will read. Larger bodies are answered
+    /// with 413 and the connection is closed. Defaults to 8 MiB; `None`
+    /// reads bodies of any length a client declares into memory.
+    pub max_body_size: Option<usize>,
+    /// Decodes `Content-Encoding: gzip` request bodies for handlers, up to
+    /// this many decoded bytes. Larger bodies are answered with 413. `None`
+    /// passes bodies on as sent.
+    pub max_decompressed_body_size: Option<usize>,
+    /// Accept request heads with bare LF line endings and whitespace before
+    /// header colons, as typed by hand over telnet. Off by default, as a
+    /// proxy in front may split such a request differently.
+    pub lenient_parsing: bool,
+    /// Answer text responses with 406 when the client's `Accept-Charset`
+    /// rules out UTF-8, rather than sending UTF-8 anyway.
+    pub strict_charset: bool,
+    /// Where served requests are recorded. `None` disables access logging.
+    pub access_log: Option<AccessLog>,
+    /// Counts bytes transferred per route and per client.
+    pub transfer_accounting: Option<Arc<TransferAccounting>>,
+    /// Request and byte quotas per client. Exhausted quotas are answered
+    /// with 429.
+    pub quotas: Option<Quotas>,
+    /// Flags handlers can query with [`crate::http::Request::flag`].
+    pub feature_flags: Option<Arc<dyn FeatureFlags>>,
+    /// A/B experiments, whose variants handlers read with
+    /// [`crate::http::Request::variant`].
+    pub experiments: Option<Experiments>,
+    /// Cores to pin threads to, on Linux. Pooled workers are pinned to them
+    /// in turn, and thread-per-core runs one acceptor on each listed core
+    /// instead of on every available one. `None` or an empty list leaves
+    /// pooled workers to the scheduler. Ignored, with a warning, where
+    /// pinning is unsupported.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// The time source for request durations and the watchdog.
+    pub clock: Arc<dyn Clock>,
+    /// Detects a wedged accept loop or starved worker pool. `None` disables
+    /// the watchdog.
+    pub watchdog: Option<Watchdog>,
+    #[cfg(feature = "alloc-tracking")]
+    pub allocation_budget: Option<AllocBudget>,
 }
 
 impl<Ctx: Send + Sync> Config<Ctx> {
+    pub fn new(port: i32, ctx: Ctx) -> Config<Ctx> {
+        Config {
+            port,
+            ctx,
+            persist_connection_for: Duration::from_secs(5),
+            tcp_nodelay: true,
+            extension_methods: vec![],
+            runtime: Runtime::Pooled,
+            max_connections_per_client: None,
+            trusted_proxies: vec![],
+            max_response_size: None,
+            response_write_timeout: None,
+            max_header_bytes: 8192,
+            header_read_timeout: Some(Duration::from_secs(10)),
+            max_target_len: 8000,
+            max_header_count: 100,
+            max_body_size: Some(8 * 1024 * 1024),
+            max_decompressed_body_size: None,
+            lenient_parsing: false,
+            strict_charset: false,
+            access_log: None,
+            transfer_accounting: None,
+            quotas: None,
+            feature_flags: None,
+            experiments: None,
+            cpu_affinity: None,
+            clock: Arc::new(SystemClock),
+            watchdog: None,
+            #[cfg(feature = "alloc-tracking")]
+            allocation_budget: None,
+        }
+    }
+
+    /// Checks settings that would otherwise fail on a worker thread at
+    /// startup: every `cpu_affinity` core must be one a thread can be
+    /// pinned to.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for core in self.cpu_affinity.iter().flatten() {
+            affinity::check_core(*core)
+                .map_err(|e| ConfigError::InvalidCpuAffinity(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
     pub fn addr(&self) -> String {
         format!("127.0.0.1:{}", self.port)
     }
+
+    pub fn accepts_method(&self, method: &ReqType) -> bool {
+        match method {
+            ReqType::Other(token) => self.extension_methods.iter().any(|m| m == token),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Runtime {
+    /// One acceptor hands connections to a shared thread pool.
+    Pooled,
+    /// Every core, or each core in `cpu_affinity`, runs its own
+    /// `SO_REUSEPORT` acceptor and a worker pool pinned to that core, with
+    /// no state shared between cores. Where
+    /// `SO_REUSEPORT` is unavailable, as on Windows, this runs as `Pooled`.
+    ThreadPerCore,
+    /// Connections are served one at a time on the thread that called
+    /// `listen`, with no pool or background threads, so runs are
+    /// deterministic. The watchdog and route timeouts are not applied.
+    Inline,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum HttpProtocol {
+    Http10,
     Http11,
 }
 
 impl fmt::Display for HttpProtocol {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            HttpProtocol::Http10 => write!(f, "HTTP/1.0"),
+            HttpProtocol::Http11 => write!(f, "HTTP/1.1"),
+        }
     }
 }
 
 impl FromStr for HttpProtocol {
     type Err = HttpProtocolParseError;
 
+    /// Later HTTP/1 minor versions are read as HTTP/1.1, which they must
+    /// stay compatible with.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "HTTP/1.1" => Ok(HttpProtocol::Http11),
-            _ => Err(HttpProtocolParseError),
+        let version: &[u8] = s
+            .strip_prefix("HTTP/")
+            .map(str::as_bytes)
+            .ok_or(HttpProtocolParseError::Malformed)?;
+
+        match version {
+            b"1.0" => Ok(HttpProtocol::Http10),
+            [b'1', b'.', minor] if minor.is_ascii_digit() => Ok(HttpProtocol::Http11),
+            [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit() => {
+                Err(HttpProtocolParseError::UnsupportedVersion)
+            }
+            _ => Err(HttpProtocolParseError::Malformed),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct HttpProtocolParseError;
+#[derive(Debug, PartialEq)]
+pub enum HttpProtocolParseError {
+    Malformed,
+    /// A well-formed version other than HTTP/1.
+    UnsupportedVersion,
+}
 
 impl fmt::Display for HttpProtocolParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Unsupported HTTP Protocol")
+        match self {
+            HttpProtocolParseError::Malformed => write!(f, "Malformed HTTP Protocol"),
+            HttpProtocolParseError::UnsupportedVersion => write!(f, "Unsupported HTTP Protocol"),
+        }
     }
 }
 
 impl Error for HttpProtocolParseError {}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Invalid CPU affinity: {0}")]
+    InvalidCpuAffinity(String),
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Counts open connections per client IP and refuses new ones once a client
+/// holds `max_per_client` of them. Trusted proxies are never limited, since
+/// their connections carry many clients.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    max_per_client: Option<usize>,
+    trusted_proxies: Vec<IpAddr>,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_per_client: Option<usize>, trusted_proxies: Vec<IpAddr>) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_per_client,
+            trusted_proxies,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reserves a connection slot for `ip`, released when the returned permit
+    /// is dropped. Returns `None` when `ip` is already at its limit.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionPermit> {
+        let max: usize = match self.max_per_client {
+            Some(max) if !self.trusted_proxies.contains(&ip) => max,
+            _ => return Some(ConnectionPermit { limiter: None, ip }),
+        };
+
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        let count: &mut usize = open.entry(ip).or_insert(0);
+
+        if *count >= max {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(ConnectionPermit {
+            limiter: Some(Arc::clone(self)),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(count) = open.get_mut(&ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                open.remove(&ip);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Option<Arc<ConnectionLimiter>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.release(self.ip);
+        }
+    }
+}
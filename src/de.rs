@@ -0,0 +1,277 @@
+use std::fmt;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use thiserror::Error;
+
+/// Deserializes `T` from string key/value pairs, such as path parameters.
+///
+/// Values are parsed into the target field type on demand, and keys that
+/// appear more than once can be collected into a sequence.
+pub fn from_pairs<'a, T, I>(pairs: I) -> Result<T, DeserializeError>
+where
+    T: DeserializeOwned,
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut entries: Vec<(String, Vec<String>)> = vec![];
+
+    for (key, val) in pairs {
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, vals)) => vals.push(String::from(val)),
+            None => entries.push((String::from(key), vec![String::from(val)])),
+        }
+    }
+
+    T::deserialize(PairsDeserializer {
+        entries: entries.into_iter(),
+        pending: None,
+    })
+}
+
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct DeserializeError(String);
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeserializeError(msg.to_string())
+    }
+}
+
+struct PairsDeserializer {
+    entries: std::vec::IntoIter<(String, Vec<String>)>,
+    pending: Option<(String, Vec<String>)>,
+}
+
+impl<'de> de::Deserializer<'de> for PairsDeserializer {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for PairsDeserializer {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.next() {
+            Some((key, vals)) => {
+                let key_de = key.clone().into_deserializer();
+                self.pending = Some((key, vals));
+                seed.deserialize(key_de).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, vals) = self
+            .pending
+            .take()
+            .ok_or_else(|| DeserializeError(String::from("value requested before key")))?;
+
+        seed.deserialize(ValueDeserializer { vals })
+            .map_err(|e| DeserializeError(format!("`{}`: {}", key, e)))
+    }
+}
+
+struct ValueDeserializer {
+    vals: Vec<String>,
+}
+
+impl ValueDeserializer {
+    fn single(self) -> Result<String, DeserializeError> {
+        let mut vals = self.vals;
+
+        match vals.len() {
+            1 => Ok(vals.remove(0)),
+            0 => Err(DeserializeError(String::from("missing value"))),
+            _ => Err(DeserializeError(String::from(
+                "expected a single value, found several",
+            ))),
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                let val: String = self.single()?;
+                match val.parse() {
+                    Ok(parsed) => visitor.$visit(parsed),
+                    Err(_) => Err(DeserializeError(format!("invalid value `{}`", val))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.vals.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            visitor.visit_string(self.single()?)
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.single()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.single()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_byte_buf(self.single()?.into_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_byte_buf(self.single()?.into_bytes())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(ValuesAccess {
+            vals: self.vals.into_iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(DeserializeError(String::from(
+            "nested maps are not supported",
+        )))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(DeserializeError(String::from(
+            "nested structs are not supported",
+        )))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_enum(
+            self.single()?.into_deserializer(),
+            name,
+            variants,
+            visitor,
+        )
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.single()?)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct ValuesAccess {
+    vals: std::vec::IntoIter<String>,
+}
+
+impl<'de> SeqAccess<'de> for ValuesAccess {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.vals.next() {
+            Some(val) => seed
+                .deserialize(ValueDeserializer { vals: vec![val] })
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.vals.len())
+    }
+}
@@ -0,0 +1,67 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// Something that happened in a running server, as seen by subscribers of
+/// [`RsttpServer::subscribe`](crate::rsttp_server::RsttpServer::subscribe).
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    ConnectionOpened {
+        client: SocketAddr,
+    },
+    ConnectionClosed {
+        client: SocketAddr,
+    },
+    RequestCompleted {
+        client: Option<SocketAddr>,
+        method: String,
+        path: String,
+        status: u16,
+        duration: Duration,
+    },
+    Error {
+        client: Option<SocketAddr>,
+        message: String,
+    },
+}
+
+/// Fans events out to every subscriber. Subscribers whose receiver has been
+/// dropped are forgotten on the next event.
+#[derive(Debug, Default)]
+pub(crate) struct EventBus {
+    subscribers: Mutex<Vec<Sender<ServerEvent>>>,
+}
+
+impl EventBus {
+    pub(crate) fn subscribe(&self) -> Receiver<ServerEvent> {
+        let (sender, receiver) = mpsc::channel();
+
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+
+        receiver
+    }
+
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.subscribers
+            .lock()
+            .is_ok_and(|subscribers| !subscribers.is_empty())
+    }
+
+    /// Sends the event built by `event` to all subscribers. It is only built
+    /// when someone is subscribed.
+    pub(crate) fn publish(&self, event: impl FnOnce() -> ServerEvent) {
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            return;
+        };
+
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let event: ServerEvent = event();
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
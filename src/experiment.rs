@@ -0,0 +1,153 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::Request;
+use crate::sha256;
+use crate::transfer::ClientIdentity;
+
+const COOKIE_MAX_AGE_SECS: u64 = 365 * 86_400;
+
+/// What a request is bucketed by. The same id always lands in the same
+/// variant of an experiment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    /// The client, as [`ClientIdentity`] tells it, e.g. a user id header.
+    Client(ClientIdentity),
+    /// An id kept in this cookie. Requests without it are given a new,
+    /// random id, which is set on the response.
+    Cookie(String),
+}
+
+/// An experiment and the share of subjects each variant gets.
+#[derive(Debug, Clone)]
+struct Experiment {
+    name: String,
+    variants: Vec<(String, u64)>,
+}
+
+/// A/B experiments whose variants are assigned per request and read with
+/// [`Request::variant`].
+#[derive(Debug)]
+pub struct Experiments {
+    subject: Subject,
+    experiments: Vec<Experiment>,
+}
+
+/// The variants a request was assigned, and the cookie carrying a newly
+/// issued subject id.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Assignment {
+    pub(crate) variants: Vec<(String, String)>,
+    pub(crate) set_cookie: Option<String>,
+}
+
+impl Experiments {
+    pub fn new(subject: Subject) -> Experiments {
+        Experiments {
+            subject,
+            experiments: vec![],
+        }
+    }
+
+    /// Adds the experiment `name`, splitting subjects between `variants` by
+    /// weight: `[("control", 90), ("new-search", 10)]`. Variants with no
+    /// weight are never assigned.
+    pub fn experiment(&mut self, name: &str, variants: &[(&str, u32)]) -> &mut Self {
+        self.experiments
+            .retain(|experiment| experiment.name != name);
+        self.experiments.push(Experiment {
+            name: String::from(name),
+            variants: variants
+                .iter()
+                .map(|(variant, weight)| (String::from(*variant), u64::from(*weight)))
+                .collect(),
+        });
+        self
+    }
+
+    /// The cookie subjects are kept in, which responses that depend on a
+    /// variant vary by.
+    pub(crate) fn cookie_name(&self) -> Option<&str> {
+        match &self.subject {
+            Subject::Cookie(name) => Some(name),
+            Subject::Client(_) => None,
+        }
+    }
+
+    pub(crate) fn assign(&self, req: &Request) -> Assignment {
+        let (subject, set_cookie) = match &self.subject {
+            Subject::Client(identity) => match identity.of(req) {
+                Some(id) => (id, None),
+                None => return Assignment::default(),
+            },
+            Subject::Cookie(name) => match req.cookies().remove(name) {
+                Some(id) if !id.is_empty() => (id, None),
+                _ => {
+                    let id: String = new_subject_id();
+                    let cookie: String = format!(
+                        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+                        name, id, COOKIE_MAX_AGE_SECS
+                    );
+                    (id, Some(cookie))
+                }
+            },
+        };
+
+        let variants: Vec<(String, String)> = self
+            .experiments
+            .iter()
+            .filter_map(|experiment| {
+                let variant: &str = experiment.variant_for(&subject)?;
+                Some((experiment.name.clone(), String::from(variant)))
+            })
+            .collect();
+
+        Assignment {
+            variants,
+            set_cookie,
+        }
+    }
+}
+
+impl Experiment {
+    fn variant_for(&self, subject: &str) -> Option<&str> {
+        let total: u64 = self.variants.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return None;
+        }
+
+        // Hashing the experiment name in keeps one experiment's buckets
+        // independent of another's for the same subject.
+        let digest: [u8; 32] = sha256::digest(format!("{}:{}", self.name, subject).as_bytes());
+        let mut prefix: [u8; 8] = [0; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        let mut point: u64 = u64::from_be_bytes(prefix) % total;
+
+        self.variants.iter().find_map(|(variant, weight)| {
+            if point < *weight {
+                return Some(variant.as_str());
+            }
+
+            point -= weight;
+            None
+        })
+    }
+}
+
+/// An id unlikely to be handed out twice. It is not secret, only spread.
+fn new_subject_id() -> String {
+    static ISSUED: AtomicU64 = AtomicU64::new(0);
+
+    let nanos: u128 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.write_u64(ISSUED.fetch_add(1, Ordering::Relaxed));
+    let high: u64 = hasher.finish();
+    hasher.write_u64(high);
+
+    format!("{:016x}{:016x}", high, hasher.finish())
+}
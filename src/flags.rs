@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, warn};
+
+use crate::http::{Request, Response};
+use crate::router::HandlerFn;
+use crate::router::params::PathParams;
+use crate::subsystem::Periodic;
+
+/// Feature flags queried per request through [`Request::flag`], so an
+/// implementation may enable a flag for some clients or tenants only.
+pub trait FeatureFlags: Send + Sync + fmt::Debug {
+    fn is_enabled(&self, flag: &str, req: &Request) -> bool;
+}
+
+/// Flags that are either on or off for every request, kept in memory and
+/// changeable while the server runs.
+#[derive(Debug, Default)]
+pub struct MemoryFlags {
+    flags: RwLock<HashMap<String, bool>>,
+}
+
+impl MemoryFlags {
+    pub fn new() -> MemoryFlags {
+        MemoryFlags::default()
+    }
+
+    pub fn set(&self, flag: &str, enabled: bool) {
+        if let Ok(mut flags) = self.flags.write() {
+            flags.insert(String::from(flag), enabled);
+        }
+    }
+
+    /// Replaces every flag at once, so requests never see a mix of old and
+    /// new values.
+    pub fn replace(&self, new_flags: HashMap<String, bool>) {
+        if let Ok(mut flags) = self.flags.write() {
+            *flags = new_flags;
+        }
+    }
+
+    /// Replaces the flags with those in the file at `path`, one
+    /// `name = on` or `name = off` per line. `#` starts a comment.
+    pub fn load(&self, path: &Path) -> io::Result<()> {
+        let flags: HashMap<String, bool> = parse_flags(&fs::read_to_string(path)?)?;
+        self.replace(flags);
+
+        Ok(())
+    }
+
+    /// A subsystem reloading the flags from `path` whenever the file has
+    /// changed, checked every `interval`. A file that fails to load leaves
+    /// the current flags in place.
+    pub fn reloader(self: &Arc<Self>, path: &Path, interval: Duration) -> Periodic {
+        let flags: Arc<MemoryFlags> = Arc::clone(self);
+        let path: PathBuf = path.to_path_buf();
+        let mut loaded: Option<SystemTime> = None;
+
+        Periodic::new("feature-flags", interval, move || {
+            let modified: Option<SystemTime> =
+                fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+
+            if modified.is_none() || modified == loaded {
+                return;
+            }
+
+            match flags.load(&path) {
+                Ok(()) => {
+                    info!(path = %path.display(), "Reloaded feature flags");
+                    loaded = modified;
+                }
+                Err(e) => warn!(
+                    path = %path.display(),
+                    error = e.to_string(),
+                    "Failed to reload feature flags"
+                ),
+            }
+        })
+    }
+}
+
+impl FeatureFlags for MemoryFlags {
+    fn is_enabled(&self, flag: &str, _req: &Request) -> bool {
+        self.flags
+            .read()
+            .is_ok_and(|flags| flags.get(flag).copied().unwrap_or(false))
+    }
+}
+
+fn parse_flags(contents: &str) -> io::Result<HashMap<String, bool>> {
+    let mut flags: HashMap<String, bool> = HashMap::new();
+
+    for (idx, line) in contents.lines().enumerate() {
+        let line: &str = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid feature flag on line {}", idx + 1),
+            )
+        };
+
+        let (name, val) = line.split_once('=').ok_or_else(invalid)?;
+        let enabled: bool = match val.trim() {
+            "on" | "true" => true,
+            "off" | "false" => false,
+            _ => return Err(invalid()),
+        };
+
+        flags.insert(String::from(name.trim()), enabled);
+    }
+
+    Ok(flags)
+}
+
+/// Routes to `on` while `flag` is enabled for the request and to `off`
+/// otherwise: `router.get("/cart", flagged("new-cart", new_cart, cart))`.
+pub fn flagged<Ctx: 'static>(
+    flag: &str,
+    on: impl HandlerFn<Ctx>,
+    off: impl HandlerFn<Ctx>,
+) -> impl HandlerFn<Ctx> {
+    let flag: String = String::from(flag);
+
+    move |req: &Request, params: PathParams, ctx: &Ctx| -> Response {
+        match req.flag(&flag) {
+            true => on(req, params, ctx),
+            false => off(req, params, ctx),
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use std::io::{self, Read};
+
+use flate2::read::GzDecoder;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::Headers;
+use super::header;
+use crate::clock::Clock;
+
+const MAX_LINE_LEN: usize = 4096;
+
+/// Bytes read from a connection past what has been consumed so far, so the
+/// head, the body and any chunk framing can be taken from it piece by piece.
+pub(crate) struct StreamBuffer<R> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    consumed: usize,
+}
+
+impl<R: Read> StreamBuffer<R> {
+    pub(crate) fn new(reader: R) -> StreamBuffer<R> {
+        StreamBuffer {
+            reader,
+            buf: Vec::new(),
+            pos: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Reads more bytes from the connection. Returns 0 once it is closed.
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk: [u8; 8192] = [0; 8192];
+        let n: usize = self.reader.read(&mut chunk)?;
+        self.buf.extend_from_slice(&chunk[..n]);
+
+        Ok(n)
+    }
+
+    fn fill_or_eof(&mut self) -> io::Result<()> {
+        match self.fill()? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.pos += len;
+        self.consumed += len;
+    }
+
+    /// Bytes taken from the connection since the buffer was created.
+    pub(crate) fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    pub(crate) fn unread(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Reads up to and including the blank line that ends a request head,
+    /// across as many reads as it takes. Returns `None` if the connection
+    /// closes before any byte arrives. Once the head has started, it must be
+    /// complete within `timeout`; this is checked as each read returns.
+    /// With `lenient`, lines may also end in a bare LF.
+    pub(crate) fn read_head(
+        &mut self,
+        max_len: usize,
+        timeout: Option<Duration>,
+        lenient: bool,
+        clock: &dyn Clock,
+    ) -> Result<Option<Vec<u8>>, BodyError> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+
+        let mut started: Option<Instant> = None;
+
+        loop {
+            if let Some(len) = head_len(self.unread(), lenient) {
+                if len > max_len {
+                    return Err(self.head_too_large(max_len, lenient));
+                }
+
+                let head: Vec<u8> = self.unread()[..len].to_vec();
+                self.consume(len);
+                return Ok(Some(head));
+            }
+
+            if self.unread().len() > max_len {
+                return Err(self.head_too_large(max_len, lenient));
+            }
+
+            if !self.unread().is_empty() {
+                let started: Instant = *started.get_or_insert_with(|| clock.now());
+                if timeout
+                    .is_some_and(|timeout| clock.now().saturating_duration_since(started) > timeout)
+                {
+                    return Err(BodyError::HeadTimeout);
+                }
+            }
+
+            if self.fill()? == 0 {
+                return match self.unread().is_empty() {
+                    true => Ok(None),
+                    false => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                };
+            }
+        }
+    }
+
+    /// Which limit an unread head over `max_len` bytes broke: the request
+    /// line alone, or the head as a whole.
+    fn head_too_large(&self, max_len: usize, lenient: bool) -> BodyError {
+        let prefix: &[u8] = &self.unread()[..max_len.min(self.unread().len())];
+        let line_end: &[u8] = if lenient { b"\n" } else { b"\r\n" };
+
+        match find(prefix, line_end) {
+            Some(_) => BodyError::HeadTooLarge,
+            None => BodyError::RequestLineTooLong,
+        }
+    }
+
+    /// Reads a CRLF-terminated line, without the CRLF.
+    fn read_line(&mut self) -> Result<Vec<u8>, BodyError> {
+        loop {
+            if let Some(end) = find(self.unread(), b"\r\n") {
+                let line: Vec<u8> = self.unread()[..end].to_vec();
+                self.consume(end + 2);
+                return Ok(line);
+            }
+
+            if self.unread().len() > MAX_LINE_LEN {
+                return Err(BodyError::LineTooLong);
+            }
+
+            self.fill_or_eof()?;
+        }
+    }
+
+    pub(crate) fn read_exact_bytes(&mut self, len: usize) -> Result<Vec<u8>, BodyError> {
+        while self.unread().len() < len {
+            self.fill_or_eof()?;
+        }
+
+        let bytes: Vec<u8> = self.unread()[..len].to_vec();
+        self.consume(len);
+
+        Ok(bytes)
+    }
+
+    /// Decodes a `Transfer-Encoding: chunked` body, up to and including the
+    /// terminating zero-size chunk and the trailer fields after it, of which
+    /// there may be at most `max_trailers`. Fails as soon as a chunk would
+    /// take the body past `max_len`.
+    pub(crate) fn read_chunked(
+        &mut self,
+        max_len: Option<usize>,
+        max_trailers: usize,
+    ) -> Result<(Vec<u8>, Headers), BodyError> {
+        let mut body: Vec<u8> = Vec::new();
+
+        loop {
+            let line: Vec<u8> = self.read_line()?;
+            let size: usize = parse_chunk_size(&line)?;
+
+            if size == 0 {
+                break;
+            }
+
+            if max_len.is_some_and(|max| body.len().saturating_add(size) > max) {
+                return Err(BodyError::BodyTooLarge);
+            }
+
+            body.extend_from_slice(&self.read_exact_bytes(size)?);
+
+            if !self.read_line()?.is_empty() {
+                return Err(BodyError::MissingChunkTerminator);
+            }
+        }
+
+        let mut trailers: Headers = Headers::new();
+
+        loop {
+            let line: Vec<u8> = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+
+            if trailers.len() == max_trailers {
+                return Err(BodyError::TooManyTrailers);
+            }
+
+            let (name, val) = parse_trailer(&line)?;
+            trailers.append(name, String::from(val));
+        }
+
+        Ok((body, trailers))
+    }
+}
+
+/// Decodes a gzip body, failing once it decodes to more than `max_len`
+/// bytes so a small body can't expand without bound.
+pub(crate) fn gunzip(data: &[u8], max_len: usize) -> Result<Vec<u8>, BodyError> {
+    let mut decoded: Vec<u8> = Vec::new();
+    GzDecoder::new(data)
+        .take((max_len as u64).saturating_add(1))
+        .read_to_end(&mut decoded)
+        .map_err(|_| BodyError::InvalidGzip)?;
+
+    match decoded.len() > max_len {
+        true => Err(BodyError::BodyTooLarge),
+        false => Ok(decoded),
+    }
+}
+
+/// Splits a trailer field line into its name and value.
+fn parse_trailer(line: &[u8]) -> Result<(&str, &str), BodyError> {
+    let line: &str = std::str::from_utf8(line).map_err(|_| BodyError::InvalidTrailer)?;
+
+    header::parse_field(line, false).ok_or(BodyError::InvalidTrailer)
+}
+
+/// Parses the hex size at the start of a chunk-size line, ignoring any chunk
+/// extensions after `;`.
+fn parse_chunk_size(line: &[u8]) -> Result<usize, BodyError> {
+    let size: &[u8] = match line.iter().position(|b| *b == b';') {
+        Some(idx) => &line[..idx],
+        None => line,
+    };
+    let size: &str = std::str::from_utf8(size)
+        .map_err(|_| BodyError::InvalidChunkSize)?
+        .trim_end_matches([' ', '\t']);
+
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(BodyError::InvalidChunkSize);
+    }
+
+    usize::from_str_radix(size, 16).map_err(|_| BodyError::InvalidChunkSize)
+}
+
+/// The length of the head at the start of `data`, up to and including the
+/// blank line ending it. With `lenient`, either line ending of that blank
+/// line, and of the one before it, may be a bare LF.
+pub(crate) fn head_len(data: &[u8], lenient: bool) -> Option<usize> {
+    if !lenient {
+        return find(data, b"\r\n\r\n").map(|end| end + 4);
+    }
+
+    data.iter().enumerate().find_map(|(idx, b)| {
+        let rest: &[u8] = &data[idx + 1..];
+
+        match *b == b'\n' {
+            true if rest.starts_with(b"\n") => Some(idx + 2),
+            true if rest.starts_with(b"\r\n") => Some(idx + 3),
+            _ => None,
+        }
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum BodyError {
+    #[error("Request head is too large")]
+    HeadTooLarge,
+
+    #[error("Request line is too long")]
+    RequestLineTooLong,
+
+    #[error("Request head was not received in time")]
+    HeadTimeout,
+
+    #[error("Request body is too large")]
+    BodyTooLarge,
+
+    #[error("Line in chunked body is too long")]
+    LineTooLong,
+
+    #[error("Invalid chunk size")]
+    InvalidChunkSize,
+
+    #[error("Chunk data is not followed by CRLF")]
+    MissingChunkTerminator,
+
+    #[error("Body is not valid gzip")]
+    InvalidGzip,
+
+    #[error("Invalid trailer field")]
+    InvalidTrailer,
+
+    #[error("Too many trailer fields")]
+    TooManyTrailers,
+
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gunzip_without_limit_decodes_whole_body() {
+        assert_eq!(gunzip(&gzip(b"hello"), usize::MAX).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn gunzip_stops_past_limit() {
+        assert_eq!(gunzip(&gzip(b"hello"), 5).unwrap(), b"hello");
+        assert!(matches!(
+            gunzip(&gzip(b"hello"), 4),
+            Err(BodyError::BodyTooLarge)
+        ));
+    }
+
+    /// Hands out one byte per read, advancing `clock` a second each time.
+    struct SlowReader<'a> {
+        bytes: &'a [u8],
+        clock: &'a ManualClock,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.clock.advance(Duration::from_secs(1));
+
+            match self.bytes.split_first() {
+                Some((byte, rest)) => {
+                    buf[0] = *byte;
+                    self.bytes = rest;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn read_head_times_out_by_the_given_clock() {
+        let clock: ManualClock = ManualClock::new();
+        let head: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+        let mut buffer = StreamBuffer::new(SlowReader {
+            bytes: head,
+            clock: &clock,
+        });
+        let timeout: Option<Duration> = Some(Duration::from_secs(5));
+        assert!(matches!(
+            buffer.read_head(1024, timeout, false, &clock),
+            Err(BodyError::HeadTimeout)
+        ));
+
+        let mut buffer = StreamBuffer::new(SlowReader {
+            bytes: head,
+            clock: &clock,
+        });
+        let timeout: Option<Duration> = Some(Duration::from_secs(60));
+        assert_eq!(
+            buffer.read_head(1024, timeout, false, &clock).unwrap(),
+            Some(head.to_vec())
+        );
+    }
+
+    /// Whether an error is the one a case expects.
+    type Expected = fn(&BodyError) -> bool;
+
+    fn read_chunked(input: &[u8]) -> Result<(Vec<u8>, Headers), BodyError> {
+        StreamBuffer::new(input).read_chunked(Some(16), 2)
+    }
+
+    #[test]
+    fn chunked_bodies_are_decoded() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"5\r\nhello\r\n0\r\n\r\n", b"hello"),
+            (b"3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n", b"hello"),
+            (b"5;name=val\r\nhello\r\n0;last\r\n\r\n", b"hello"),
+            (b"5 \r\nhello\r\n0\r\n\r\n", b"hello"),
+        ];
+
+        for (input, body) in cases {
+            let (decoded, trailers) = read_chunked(input).unwrap();
+            assert_eq!(decoded, body, "{:?}", String::from_utf8_lossy(input));
+            assert!(trailers.is_empty());
+        }
+    }
+
+    #[test]
+    fn chunked_trailers_are_kept() {
+        let (body, trailers) =
+            read_chunked(b"2\r\nhi\r\n0\r\nX-Sum: 1\r\nX-Sum: 2\r\n\r\n").unwrap();
+
+        assert_eq!(body, b"hi");
+        assert_eq!(trailers.get_all("X-Sum").collect::<Vec<_>>(), ["1", "2"]);
+    }
+
+    #[test]
+    fn malformed_chunked_bodies_are_rejected() {
+        let long_line: Vec<u8> = vec![b'1'; MAX_LINE_LEN + 1];
+        let cases: [(&[u8], Expected); 12] = [
+            (b"zz\r\n", |e| matches!(e, BodyError::InvalidChunkSize)),
+            (b"\r\n", |e| matches!(e, BodyError::InvalidChunkSize)),
+            (b";ext\r\n", |e| matches!(e, BodyError::InvalidChunkSize)),
+            (b"+5\r\nhello\r\n", |e| {
+                matches!(e, BodyError::InvalidChunkSize)
+            }),
+            (b"0x5\r\nhello\r\n", |e| {
+                matches!(e, BodyError::InvalidChunkSize)
+            }),
+            (b"1ffffffffffffffff\r\n", |e| {
+                matches!(e, BodyError::InvalidChunkSize)
+            }),
+            (b"5\r\nhelloX\r\n0\r\n\r\n", |e| {
+                matches!(e, BodyError::MissingChunkTerminator)
+            }),
+            (b"11\r\n", |e| matches!(e, BodyError::BodyTooLarge)),
+            (b"9\r\nhello the\r\n9\r\n", |e| {
+                matches!(e, BodyError::BodyTooLarge)
+            }),
+            (b"0\r\nno colon\r\n\r\n", |e| {
+                matches!(e, BodyError::InvalidTrailer)
+            }),
+            (b"0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", |e| {
+                matches!(e, BodyError::TooManyTrailers)
+            }),
+            (
+                b"5\r\nhel",
+                |e| matches!(e, BodyError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            match read_chunked(input) {
+                Err(e) => assert!(expected(&e), "{:?}: {}", String::from_utf8_lossy(input), e),
+                Ok(_) => panic!("{:?} was accepted", String::from_utf8_lossy(input)),
+            }
+        }
+
+        assert!(matches!(
+            read_chunked(&long_line),
+            Err(BodyError::LineTooLong)
+        ));
+    }
+}
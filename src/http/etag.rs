@@ -0,0 +1,211 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use super::header::HttpHeader;
+use super::{ReqType, Request, StatusCode};
+use crate::sha256;
+
+/// An entity tag, as sent in `ETag` and matched by `If-Match` and
+/// `If-None-Match`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    pub weak: bool,
+    /// The opaque tag, without quotes.
+    pub tag: String,
+}
+
+impl ETag {
+    pub fn strong(tag: &str) -> ETag {
+        ETag {
+            weak: false,
+            tag: String::from(tag),
+        }
+    }
+
+    pub fn weak(tag: &str) -> ETag {
+        ETag {
+            weak: true,
+            tag: String::from(tag),
+        }
+    }
+
+    /// A strong tag derived from the SHA-256 of `content`.
+    pub fn of(content: &[u8]) -> ETag {
+        ETag::strong(&sha256::hex_digest(content)[..32])
+    }
+
+    /// Both tags are strong and identical. Used for `If-Match` and
+    /// `If-Range`.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are identical, ignoring weakness. Used for `If-None-Match`.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl HttpHeader for ETag {
+    fn key(&self) -> &str {
+        "ETag"
+    }
+
+    fn val(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.weak {
+            true => write!(f, "W/\"{}\"", self.tag),
+            false => write!(f, "\"{}\"", self.tag),
+        }
+    }
+}
+
+impl FromStr for ETag {
+    type Err = ETagParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s: &str = s.trim();
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, s),
+        };
+
+        let tag: &str = quoted
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .ok_or_else(|| ETagParseError(String::from(s)))?;
+
+        // etagc is any visible character but `"`, or obs-text.
+        if tag.bytes().any(|b| b == b'"' || b <= b' ' || b == 0x7f) {
+            return Err(ETagParseError(String::from(s)));
+        }
+
+        Ok(ETag {
+            weak,
+            tag: String::from(tag),
+        })
+    }
+}
+
+/// The value of an `If-Match` or `If-None-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ETagList {
+    /// `*`, matching any current representation.
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl ETagList {
+    /// Whether `If-Match` with this list lets a request on a representation
+    /// tagged `current` proceed. It never does when there is no current
+    /// representation.
+    pub fn matches_if_match(&self, current: Option<&ETag>) -> bool {
+        match (self, current) {
+            (_, None) => false,
+            (ETagList::Any, Some(_)) => true,
+            (ETagList::Tags(tags), Some(current)) => tags.iter().any(|tag| tag.strong_eq(current)),
+        }
+    }
+
+    /// Whether `If-None-Match` with this list matches `current`, meaning a
+    /// `GET` should be answered with 304 and other methods with 412.
+    pub fn matches_if_none_match(&self, current: Option<&ETag>) -> bool {
+        match (self, current) {
+            (_, None) => false,
+            (ETagList::Any, Some(_)) => true,
+            (ETagList::Tags(tags), Some(current)) => tags.iter().any(|tag| tag.weak_eq(current)),
+        }
+    }
+}
+
+impl fmt::Display for ETagList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ETagList::Any => write!(f, "*"),
+            ETagList::Tags(tags) => {
+                let tags: Vec<String> = tags.iter().map(ETag::to_string).collect();
+                write!(f, "{}", tags.join(", "))
+            }
+        }
+    }
+}
+
+impl FromStr for ETagList {
+    type Err = ETagParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "*" {
+            return Ok(ETagList::Any);
+        }
+
+        // Tags may contain commas, so the list is split after each closing
+        // quote rather than on every comma.
+        let mut tags: Vec<ETag> = vec![];
+        let mut rest: &str = s;
+
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ',']);
+            if rest.is_empty() {
+                break;
+            }
+
+            let open: usize = rest
+                .find('"')
+                .ok_or_else(|| ETagParseError(String::from(s)))?;
+            let close: usize = rest[open + 1..]
+                .find('"')
+                .ok_or_else(|| ETagParseError(String::from(s)))?
+                + open
+                + 1;
+
+            tags.push(rest[..=close].parse()?);
+            rest = &rest[close + 1..];
+        }
+
+        if tags.is_empty() {
+            return Err(ETagParseError(String::from(s)));
+        }
+
+        Ok(ETagList::Tags(tags))
+    }
+}
+
+/// Evaluates `If-Match` and then `If-None-Match` on `req` against the
+/// representation tagged `current`, in the order RFC 9110 gives. Returns
+/// the status to answer with instead, 304 or 412, or `None` if the request
+/// should go ahead. Malformed headers are ignored.
+pub fn evaluate_preconditions(req: &Request, current: Option<&ETag>) -> Option<StatusCode> {
+    if let Some(Ok(if_match)) = req.if_match() {
+        if !if_match.matches_if_match(current) {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+
+    if let Some(Ok(if_none_match)) = req.if_none_match() {
+        if if_none_match.matches_if_none_match(current) {
+            let is_read: bool = match &req.req_type {
+                ReqType::Get => true,
+                ReqType::Other(method) => method == "HEAD",
+                _ => false,
+            };
+
+            return Some(match is_read {
+                true => StatusCode::NOT_MODIFIED,
+                false => StatusCode::PRECONDITION_FAILED,
+            });
+        }
+    }
+
+    None
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid entity tag: {0}")]
+pub struct ETagParseError(String);
@@ -0,0 +1,168 @@
+use std::net::{IpAddr, SocketAddr};
+
+use super::header::Headers;
+
+/// The address of the client behind any trusted proxies that `peer` is.
+///
+/// The hops a request passed through are taken from `Forwarded`, or from
+/// `X-Forwarded-For` without it, and walked from the nearest back. The
+/// first hop that isn't a trusted proxy is the client. An entry that isn't
+/// an address, such as `unknown` or an obfuscated identifier, ends the walk
+/// at the proxy that reported it, as nothing further back can be checked.
+pub(crate) fn client_addr(peer: IpAddr, headers: &Headers, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+
+    let hops: Vec<&str> = match headers.get("Forwarded") {
+        Some(_) => headers
+            .get_all("Forwarded")
+            .flat_map(|value| value.split(','))
+            .filter_map(forwarded_for)
+            .collect(),
+        None => headers
+            .get_all("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect(),
+    };
+
+    let mut client: IpAddr = peer;
+
+    for hop in hops.iter().rev() {
+        let Some(addr) = parse_node(hop) else {
+            break;
+        };
+
+        client = addr;
+        if !trusted.contains(&addr) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// The `for=` value of one `Forwarded` element, unquoted.
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        match name.trim().eq_ignore_ascii_case("for") {
+            true => Some(value.trim().trim_matches('"')),
+            false => None,
+        }
+    })
+}
+
+/// An address with an optional port, IPv6 ones in brackets when a port is
+/// given (`192.0.2.1`, `192.0.2.1:80`, `[2001:db8::1]:80`, `2001:db8::1`).
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "10.0.0.1";
+    const PROXY: &str = "10.0.0.2";
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    fn client(fields: &[(&str, &str)]) -> IpAddr {
+        let mut headers: Headers = Headers::new();
+        for (name, val) in fields {
+            headers.append(name, String::from(*val));
+        }
+
+        client_addr(ip(PEER), &headers, &[ip(PEER), ip(PROXY)])
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let headers: Headers = Headers::new();
+
+        assert_eq!(
+            client_addr(ip("192.0.2.9"), &headers, &[ip(PEER)]),
+            ip("192.0.2.9")
+        );
+    }
+
+    #[test]
+    fn walks_x_forwarded_for_past_trusted_proxies() {
+        assert_eq!(
+            client(&[("X-Forwarded-For", "192.0.2.1, 10.0.0.2")]),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client(&[
+                ("X-Forwarded-For", "192.0.2.1"),
+                ("X-Forwarded-For", "10.0.0.2")
+            ]),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn untrusted_hop_in_the_middle_ends_the_walk() {
+        assert_eq!(
+            client(&[("X-Forwarded-For", "192.0.2.1, 198.51.100.7, 10.0.0.2")]),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            client(&[("Forwarded", "for=192.0.2.1, for=198.51.100.7, for=10.0.0.2")]),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn quoted_forwarded_values_are_read() {
+        assert_eq!(
+            client(&[("Forwarded", r#"for="[2001:db8::1]:4711";proto=https"#)]),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            client(&[("Forwarded", r#"proto=http;FOR="192.0.2.1:80""#)]),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_for() {
+        assert_eq!(
+            client(&[
+                ("X-Forwarded-For", "198.51.100.7"),
+                ("Forwarded", "for=192.0.2.1")
+            ]),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn unverifiable_hops_stop_at_the_reporting_proxy() {
+        for (name, val) in [
+            ("X-Forwarded-For", "192.0.2.1, unknown, 10.0.0.2"),
+            ("X-Forwarded-For", "192.0.2.1, , 10.0.0.2"),
+            ("Forwarded", r#"for=192.0.2.1, for="_hidden", for=10.0.0.2"#),
+        ] {
+            assert_eq!(client(&[(name, val)]), ip(PROXY), "{}: {}", name, val);
+        }
+    }
+
+    #[test]
+    fn empty_or_garbage_headers_leave_the_peer() {
+        for (name, val) in [
+            ("X-Forwarded-For", ""),
+            ("X-Forwarded-For", "not an address"),
+            ("Forwarded", ""),
+            ("Forwarded", "for="),
+        ] {
+            assert_eq!(client(&[(name, val)]), ip(PEER), "{}: {:?}", name, val);
+        }
+    }
+}
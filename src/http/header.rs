@@ -1,3 +1,7 @@
+use std::fmt;
+
+use super::request::is_token;
+
 pub trait HttpHeader {
     fn key(&self) -> &str;
     fn val(&self) -> String;
@@ -6,3 +10,226 @@ pub trait HttpHeader {
         format!("{}: {}\r\n", self.key(), self.val())
     }
 }
+
+const KNOWN_HEADER_NAMES: [&str; 24] = [
+    "Accept",
+    "Accept-Charset",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Allow",
+    "Authorization",
+    "Cache-Control",
+    "Connection",
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Type",
+    "Cookie",
+    "Expect",
+    "Host",
+    "If-Match",
+    "If-Modified-Since",
+    "If-None-Match",
+    "Location",
+    "Origin",
+    "Range",
+    "Referer",
+    "Transfer-Encoding",
+    "Upgrade",
+    "User-Agent",
+];
+
+/// A header field name compared case-insensitively. Common names are
+/// interned and don't allocate.
+#[derive(Debug, Clone)]
+pub enum HeaderName {
+    Known(&'static str),
+    Custom(String),
+}
+
+impl HeaderName {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HeaderName::Known(name) => name,
+            HeaderName::Custom(name) => name,
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.as_str().eq_ignore_ascii_case(name)
+    }
+}
+
+impl From<&str> for HeaderName {
+    fn from(name: &str) -> Self {
+        match KNOWN_HEADER_NAMES
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(name))
+        {
+            Some(known) => HeaderName::Known(known),
+            None => HeaderName::Custom(String::from(name)),
+        }
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &Self) -> bool {
+        self.matches(other.as_str())
+    }
+}
+
+impl fmt::Display for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Header fields stored in arrival order. A name may appear more than once;
+/// [`Headers::get`] gives its first value and [`Headers::get_all`] every one.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(HeaderName, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Headers {
+        Headers {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.matches(name))
+            .map(|(_, val)| val)
+    }
+
+    pub fn get_all(&self, name: &str) -> impl Iterator<Item = &String> {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.matches(name))
+            .map(|(_, val)| val)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to `val`, replacing all of its existing values.
+    pub fn insert(&mut self, name: &str, val: String) {
+        self.remove(name);
+        self.append(name, val);
+    }
+
+    /// Adds a value for `name`, keeping any it already has.
+    pub fn append(&mut self, name: &str, val: String) {
+        self.entries.push((HeaderName::from(name), val));
+    }
+
+    /// Removes every value of `name`, returning the first.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let idx: usize = self.entries.iter().position(|(key, _)| key.matches(name))?;
+        let first: String = self.entries.remove(idx).1;
+        self.entries.retain(|(key, _)| !key.matches(name));
+
+        Some(first)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&HeaderName, &String)> {
+        self.entries.iter().map(|(key, val)| (key, val))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Splits a `name: value` field line, trimming the whitespace around the
+/// value, and with `lenient` also between the name and the colon. Fails
+/// for a name that is not a token and for a value holding control
+/// characters, such as a CR, LF or NUL that could be replayed as a header
+/// of its own further down the line.
+pub(crate) fn parse_field(line: &str, lenient: bool) -> Option<(&str, &str)> {
+    let (name, val) = line.split_once(':')?;
+    let name: &str = match lenient {
+        true => name.trim_end_matches([' ', '\t']),
+        false => name,
+    };
+    let val: &str = val.trim_matches([' ', '\t']);
+
+    let valid_name: bool = is_token(name);
+    let valid_val: bool = val.bytes().all(|b| b == b'\t' || !b.is_ascii_control());
+
+    (valid_name && valid_val).then_some((name, val))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_name_and_trimmed_value() {
+        assert_eq!(
+            parse_field("Host: example.com", false),
+            Some(("Host", "example.com"))
+        );
+        assert_eq!(parse_field("X-A:\t a b \t", false), Some(("X-A", "a b")));
+        assert_eq!(parse_field("X-Empty:", false), Some(("X-Empty", "")));
+        assert_eq!(
+            parse_field("X-Url: http://a:80/", false),
+            Some(("X-Url", "http://a:80/"))
+        );
+    }
+
+    #[test]
+    fn space_before_colon_is_only_accepted_leniently() {
+        assert_eq!(parse_field("Host : example.com", false), None);
+        assert_eq!(parse_field("Host\t: example.com", false), None);
+        assert_eq!(
+            parse_field("Host : example.com", true),
+            Some(("Host", "example.com"))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for line in [
+            ": value",
+            "X Y: value",
+            "X\"Y: value",
+            "X(Y): value",
+            "no colon",
+        ] {
+            assert_eq!(parse_field(line, false), None, "{:?}", line);
+            assert_eq!(parse_field(line, true), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_obs_fold() {
+        assert_eq!(parse_field(" continued: value", false), None);
+        assert_eq!(parse_field("\tcontinued: value", true), None);
+    }
+
+    #[test]
+    fn rejects_control_characters_in_value() {
+        for line in [
+            "X: a\0b",
+            "X: a\rb",
+            "X: a\nSet-Cookie: b",
+            "X: \x7f",
+            "X: a\x1bb",
+        ] {
+            assert_eq!(parse_field(line, false), None, "{:?}", line);
+            assert_eq!(parse_field(line, true), None, "{:?}", line);
+        }
+    }
+}
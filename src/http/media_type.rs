@@ -0,0 +1,173 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use super::header::HttpHeader;
+
+/// A media type as sent in `Content-Type`, such as
+/// `text/html; charset=utf-8` or `multipart/form-data; boundary=X`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    /// The top-level type, lowercased.
+    pub ty: String,
+    /// The subtype, lowercased.
+    pub subtype: String,
+    /// Parameter names lowercased and values unquoted, in order.
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn new(ty: &str, subtype: &str) -> MediaType {
+        MediaType {
+            ty: ty.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params: vec![],
+        }
+    }
+
+    /// `type/subtype`, without parameters.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.ty, self.subtype)
+    }
+
+    /// Whether this is `essence`, compared case-insensitively.
+    pub fn is(&self, essence: &str) -> bool {
+        essence.split_once('/').is_some_and(|(ty, subtype)| {
+            self.ty.eq_ignore_ascii_case(ty) && self.subtype.eq_ignore_ascii_case(subtype)
+        })
+    }
+
+    /// The value of the first parameter called `name`, which is matched
+    /// case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary")
+    }
+}
+
+impl HttpHeader for MediaType {
+    fn key(&self) -> &str {
+        "Content-Type"
+    }
+
+    fn val(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.ty, self.subtype)?;
+
+        for (name, val) in &self.params {
+            match !val.is_empty() && val.bytes().all(is_tchar) {
+                true => write!(f, "; {}={}", name, val)?,
+                false => {
+                    let escaped: String = val.replace('\\', "\\\\").replace('"', "\\\"");
+                    write!(f, "; {}=\"{}\"", name, escaped)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = MediaTypeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MediaTypeParseError(String::from(s));
+
+        let (essence, mut rest) = match s.find(';') {
+            Some(idx) => (&s[..idx], &s[idx..]),
+            None => (s, ""),
+        };
+        let (ty, subtype) = essence.trim().split_once('/').ok_or_else(invalid)?;
+
+        if !is_token(ty) || !is_token(subtype) {
+            return Err(invalid());
+        }
+
+        let mut media_type: MediaType = MediaType::new(ty, subtype);
+
+        loop {
+            rest = rest.trim_start_matches([' ', '\t']);
+            let Some(after) = rest.strip_prefix(';') else {
+                break;
+            };
+            rest = after.trim_start_matches([' ', '\t']);
+
+            // Empty parameters, as in `text/plain;;charset=utf-8`, are
+            // allowed.
+            if rest.is_empty() || rest.starts_with(';') {
+                continue;
+            }
+
+            let (name, after) = rest.split_once('=').ok_or_else(invalid)?;
+            if !is_token(name) {
+                return Err(invalid());
+            }
+
+            let (val, after) = match after.strip_prefix('"') {
+                Some(quoted) => parse_quoted(quoted).ok_or_else(invalid)?,
+                None => {
+                    let end: usize = after.find([';', ' ', '\t']).unwrap_or(after.len());
+                    let val: &str = &after[..end];
+                    if !is_token(val) {
+                        return Err(invalid());
+                    }
+                    (String::from(val), &after[end..])
+                }
+            };
+
+            media_type.params.push((name.to_ascii_lowercase(), val));
+            rest = after;
+        }
+
+        match rest.is_empty() {
+            true => Ok(media_type),
+            false => Err(invalid()),
+        }
+    }
+}
+
+/// Reads a quoted string whose opening quote has been stripped, returning
+/// its unescaped content and what follows the closing quote.
+fn parse_quoted(s: &str) -> Option<(String, &str)> {
+    let mut val: String = String::new();
+    let mut chars = s.char_indices();
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Some((val, &s[idx + 1..])),
+            '\\' => val.push(chars.next()?.1),
+            c => val.push(c),
+        }
+    }
+
+    None
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid media type: {0}")]
+pub struct MediaTypeParseError(String);
@@ -1,6 +1,18 @@
+pub(crate) mod body;
+pub mod etag;
+mod forwarded;
 mod header;
+pub mod media_type;
+pub mod negotiate;
+pub mod percent;
+pub mod range;
+pub mod redirect;
 pub mod request;
 pub mod response;
+pub mod sse;
+mod status;
 
+pub use header::{HeaderName, Headers};
 pub use request::*;
 pub use response::*;
+pub use status::{InvalidStatusCode, StatusCode};
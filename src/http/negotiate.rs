@@ -0,0 +1,67 @@
+/// Parses a comma-separated list of values with optional `;q=` weights, as
+/// sent in `Accept-Charset` or `Accept-Encoding`. Values without a weight
+/// get 1.0, and items with a malformed weight are dropped.
+pub fn parse_quality_list(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(str::trim);
+            let value: &str = params.next().filter(|value| !value.is_empty())?;
+
+            let mut q: f32 = 1.0;
+            for param in params {
+                if let Some((key, val)) = param.split_once('=') {
+                    if key.trim().eq_ignore_ascii_case("q") {
+                        q = parse_qvalue(val.trim())?;
+                    }
+                }
+            }
+
+            Some((String::from(value), q))
+        })
+        .collect()
+}
+
+/// The weight `list` gives `value`: its own entry if it has one, otherwise
+/// that of a `*` entry.
+pub fn quality_of(list: &[(String, f32)], value: &str) -> Option<f32> {
+    list.iter()
+        .find(|(v, _)| v.eq_ignore_ascii_case(value))
+        .or_else(|| list.iter().find(|(v, _)| v == "*"))
+        .map(|(_, q)| *q)
+}
+
+/// Picks the acceptable option with the highest weight, preferring earlier
+/// options on ties.
+pub fn best_match<'a>(list: &[(String, f32)], supported: &[&'a str]) -> Option<&'a str> {
+    let mut best: Option<(&'a str, f32)> = None;
+
+    for option in supported {
+        let q: f32 = quality_of(list, option).unwrap_or(0.0);
+
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((option, q));
+        }
+    }
+
+    best.map(|(option, _)| option)
+}
+
+/// Parses `0`, `1` or a decimal in between with at most three digits.
+fn parse_qvalue(s: &str) -> Option<f32> {
+    let valid: bool = match s.split_once('.') {
+        Some((int, frac)) => {
+            matches!(int, "0" | "1")
+                && frac.len() <= 3
+                && frac.bytes().all(|b| b.is_ascii_digit())
+                && (int == "0" || frac.bytes().all(|b| b == b'0'))
+        }
+        None => matches!(s, "0" | "1"),
+    };
+
+    if !valid {
+        return None;
+    }
+
+    s.parse().ok()
+}
@@ -0,0 +1,174 @@
+use thiserror::Error;
+
+/// Decodes `%XX` escapes in `s`. The decoded bytes must be valid UTF-8.
+pub fn percent_decode(s: &str) -> Result<String, PercentDecodeError> {
+    let bytes: &[u8] = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex: &[u8] = bytes
+                .get(i + 1..i + 3)
+                .ok_or(PercentDecodeError::TruncatedEscape)?;
+
+            match (hex_val(hex[0]), hex_val(hex[1])) {
+                (Some(hi), Some(lo)) => decoded.push(hi << 4 | lo),
+                _ => return Err(PercentDecodeError::InvalidEscape),
+            }
+
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| PercentDecodeError::InvalidUtf8)
+}
+
+/// Escapes every byte of `s` outside the RFC 3986 `pchar` set, so the result
+/// can be used as a single path segment.
+pub fn percent_encode_segment(s: &str) -> String {
+    let mut encoded: String = String::with_capacity(s.len());
+
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric()
+            || matches!(
+                b,
+                b'-' | b'.'
+                    | b'_'
+                    | b'~'
+                    | b'!'
+                    | b'$'
+                    | b'&'
+                    | b'\''
+                    | b'('
+                    | b')'
+                    | b'*'
+                    | b'+'
+                    | b','
+                    | b';'
+                    | b'='
+                    | b':'
+                    | b'@'
+            )
+        {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+/// Escapes every byte of `s` outside the RFC 3986 unreserved set, so the
+/// result can be used as a query key or value.
+pub fn percent_encode_query_component(s: &str) -> String {
+    let mut encoded: String = String::with_capacity(s.len());
+
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+fn hex_val(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PercentDecodeError {
+    #[error("Truncated percent escape")]
+    TruncatedEscape,
+
+    #[error("Invalid percent escape")]
+    InvalidEscape,
+
+    #[error("Percent-decoded text is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b").unwrap(), "a b");
+        assert_eq!(percent_decode("%C3%A9t%c3%a9").unwrap(), "été");
+        assert_eq!(percent_decode("plain+text").unwrap(), "plain+text");
+        assert_eq!(percent_decode("").unwrap(), "");
+    }
+
+    #[test]
+    fn decodes_encoded_slash() {
+        assert_eq!(percent_decode("a%2Fb").unwrap(), "a/b");
+        assert_eq!(percent_decode("..%2f..%2fetc").unwrap(), "../../etc");
+    }
+
+    #[test]
+    fn rejects_truncated_escapes() {
+        for input in ["%", "%2", "a%", "ab%4"] {
+            assert!(
+                matches!(
+                    percent_decode(input),
+                    Err(PercentDecodeError::TruncatedEscape)
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_escapes() {
+        for input in ["%zz", "%2g", "%g2", "%%41", "% 1"] {
+            assert!(
+                matches!(
+                    percent_decode(input),
+                    Err(PercentDecodeError::InvalidEscape)
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_non_utf8_output() {
+        for input in ["%FF", "%C3", "%C3%28", "%ED%A0%80"] {
+            assert!(
+                matches!(percent_decode(input), Err(PercentDecodeError::InvalidUtf8)),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn segment_encoding_escapes_slash_and_round_trips() {
+        let segment: &str = "a/b c%?#é";
+        let encoded: String = percent_encode_segment(segment);
+
+        assert_eq!(encoded, "a%2Fb%20c%25%3F%23%C3%A9");
+        assert_eq!(percent_decode(&encoded).unwrap(), segment);
+    }
+
+    #[test]
+    fn query_component_encoding_escapes_separators() {
+        assert_eq!(percent_encode_query_component("a=b&c+d"), "a%3Db%26c%2Bd");
+        assert_eq!(percent_encode_query_component("-._~"), "-._~");
+    }
+}
@@ -0,0 +1,415 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use super::header::HttpHeader;
+
+/// One range of a `Range: bytes=...` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `first-last`, both inclusive.
+    FromTo(u64, u64),
+    /// `first-`, to the end of the representation.
+    From(u64),
+    /// `-len`, the final `len` bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// The inclusive `(first, last)` offsets this range selects in a
+    /// representation of `len` bytes, or `None` if it selects nothing.
+    /// Ranges running past the end are cut short at the last byte.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        if len == 0 {
+            return None;
+        }
+
+        match *self {
+            ByteRange::FromTo(first, last) if first < len => Some((first, last.min(len - 1))),
+            ByteRange::From(first) if first < len => Some((first, len - 1)),
+            ByteRange::Suffix(0) => None,
+            ByteRange::Suffix(suffix) => Some((len.saturating_sub(suffix), len - 1)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteRange::FromTo(first, last) => write!(f, "{}-{}", first, last),
+            ByteRange::From(first) => write!(f, "{}-", first),
+            ByteRange::Suffix(suffix) => write!(f, "-{}", suffix),
+        }
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = RangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RangeParseError::InvalidRange(String::from(s));
+        let (first, last) = s.trim().split_once('-').ok_or_else(invalid)?;
+
+        match (first, last) {
+            ("", "") => Err(invalid()),
+            ("", suffix) => Ok(ByteRange::Suffix(parse_offset(suffix).ok_or_else(invalid)?)),
+            (first, "") => Ok(ByteRange::From(parse_offset(first).ok_or_else(invalid)?)),
+            (first, last) => {
+                let first: u64 = parse_offset(first).ok_or_else(invalid)?;
+                let last: u64 = parse_offset(last).ok_or_else(invalid)?;
+
+                if last < first {
+                    return Err(invalid());
+                }
+
+                Ok(ByteRange::FromTo(first, last))
+            }
+        }
+    }
+}
+
+/// A `Range` request header. Only the `bytes` unit is supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub ranges: Vec<ByteRange>,
+}
+
+impl Range {
+    /// The ranges that select something in a representation of `len` bytes,
+    /// as inclusive offsets. Empty means the range is unsatisfiable.
+    pub fn resolve(&self, len: u64) -> Vec<(u64, u64)> {
+        self.ranges
+            .iter()
+            .filter_map(|range| range.resolve(len))
+            .collect()
+    }
+}
+
+impl HttpHeader for Range {
+    fn key(&self) -> &str {
+        "Range"
+    }
+
+    fn val(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<String> = self.ranges.iter().map(ByteRange::to_string).collect();
+
+        write!(f, "bytes={}", ranges.join(", "))
+    }
+}
+
+impl FromStr for Range {
+    type Err = RangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (unit, ranges) = s
+            .trim()
+            .split_once('=')
+            .ok_or_else(|| RangeParseError::InvalidRange(String::from(s)))?;
+
+        if !unit.trim().eq_ignore_ascii_case("bytes") {
+            return Err(RangeParseError::UnsupportedUnit(String::from(unit.trim())));
+        }
+
+        let ranges: Vec<ByteRange> = ranges
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(ByteRange::from_str)
+            .collect::<Result<_, _>>()?;
+
+        if ranges.is_empty() {
+            return Err(RangeParseError::InvalidRange(String::from(s)));
+        }
+
+        Ok(Range { ranges })
+    }
+}
+
+/// A `Content-Range` response header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentRange {
+    /// `bytes first-last/complete_length`, with an unknown length sent as
+    /// `*`.
+    Bytes {
+        first: u64,
+        last: u64,
+        complete_length: Option<u64>,
+    },
+    /// `bytes */complete_length`, sent with a 416.
+    Unsatisfied { complete_length: u64 },
+}
+
+impl HttpHeader for ContentRange {
+    fn key(&self) -> &str {
+        "Content-Range"
+    }
+
+    fn val(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentRange::Bytes {
+                first,
+                last,
+                complete_length: Some(len),
+            } => write!(f, "bytes {}-{}/{}", first, last, len),
+            ContentRange::Bytes {
+                first,
+                last,
+                complete_length: None,
+            } => write!(f, "bytes {}-{}/*", first, last),
+            ContentRange::Unsatisfied { complete_length } => {
+                write!(f, "bytes */{}", complete_length)
+            }
+        }
+    }
+}
+
+impl FromStr for ContentRange {
+    type Err = RangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RangeParseError::InvalidContentRange(String::from(s));
+
+        let (unit, rest) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return Err(RangeParseError::UnsupportedUnit(String::from(unit)));
+        }
+
+        let (range, len) = rest.trim().split_once('/').ok_or_else(invalid)?;
+        let complete_length: Option<u64> = match len {
+            "*" => None,
+            len => Some(parse_offset(len).ok_or_else(invalid)?),
+        };
+
+        if range == "*" {
+            return complete_length
+                .map(|complete_length| ContentRange::Unsatisfied { complete_length })
+                .ok_or_else(invalid);
+        }
+
+        let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+        let first: u64 = parse_offset(first).ok_or_else(invalid)?;
+        let last: u64 = parse_offset(last).ok_or_else(invalid)?;
+
+        if last < first || complete_length.is_some_and(|len| last >= len) {
+            return Err(invalid());
+        }
+
+        Ok(ContentRange::Bytes {
+            first,
+            last,
+            complete_length,
+        })
+    }
+}
+
+/// An `Accept-Ranges` response header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptRanges {
+    Bytes,
+    None,
+}
+
+impl HttpHeader for AcceptRanges {
+    fn key(&self) -> &str {
+        "Accept-Ranges"
+    }
+
+    fn val(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for AcceptRanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcceptRanges::Bytes => write!(f, "bytes"),
+            AcceptRanges::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for AcceptRanges {
+    type Err = RangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            unit if unit.eq_ignore_ascii_case("bytes") => Ok(AcceptRanges::Bytes),
+            unit if unit.eq_ignore_ascii_case("none") => Ok(AcceptRanges::None),
+            unit => Err(RangeParseError::UnsupportedUnit(String::from(unit))),
+        }
+    }
+}
+
+/// Parses a non-empty run of ASCII digits. `u64::from_str` alone would also
+/// take a leading `+`.
+fn parse_offset(s: &str) -> Option<u64> {
+    let s: &str = s.trim();
+
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RangeParseError {
+    #[error("Unsupported range unit: {0}")]
+    UnsupportedUnit(String),
+
+    #[error("Invalid range: {0}")]
+    InvalidRange(String),
+
+    #[error("Invalid content range: {0}")]
+    InvalidContentRange(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{ContentType, Request, Response, StatusCode};
+
+    fn resolve(header: &str, len: u64) -> Vec<(u64, u64)> {
+        header.parse::<Range>().unwrap().resolve(len)
+    }
+
+    fn ranged(header: &str, content: &[u8]) -> Response {
+        let raw: String = format!("GET / HTTP/1.1\r\nRange: {}\r\n\r\n", header);
+        let req: Request = Request::new(raw.as_bytes()).unwrap();
+
+        Response::ranged(&req, content.to_vec(), ContentType::ApplicationOctectStream)
+    }
+
+    #[test]
+    fn suffix_range_selects_final_bytes() {
+        assert_eq!(resolve("bytes=-3", 10), vec![(7, 9)]);
+    }
+
+    #[test]
+    fn suffix_longer_than_body_selects_whole_body() {
+        assert_eq!(resolve("bytes=-100", 10), vec![(0, 9)]);
+    }
+
+    #[test]
+    fn zero_length_suffix_is_unsatisfiable() {
+        assert_eq!(resolve("bytes=-0", 10), vec![]);
+    }
+
+    #[test]
+    fn open_range_runs_to_end() {
+        assert_eq!(resolve("bytes=4-", 10), vec![(4, 9)]);
+    }
+
+    #[test]
+    fn open_range_past_end_is_unsatisfiable() {
+        assert_eq!(resolve("bytes=10-", 10), vec![]);
+        assert_eq!(resolve("bytes=50-", 10), vec![]);
+    }
+
+    #[test]
+    fn overlong_range_is_cut_at_last_byte() {
+        assert_eq!(resolve("bytes=2-9", 10), vec![(2, 9)]);
+        assert_eq!(resolve("bytes=2-1000", 10), vec![(2, 9)]);
+    }
+
+    #[test]
+    fn nothing_is_satisfiable_in_empty_body() {
+        assert_eq!(resolve("bytes=0-0", 0), vec![]);
+        assert_eq!(resolve("bytes=0-", 0), vec![]);
+        assert_eq!(resolve("bytes=-5", 0), vec![]);
+    }
+
+    #[test]
+    fn multiple_ranges_drop_unsatisfiable_ones() {
+        assert_eq!(resolve("bytes=0-1, 20-30, -2", 10), vec![(0, 1), (8, 9)]);
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        for header in [
+            "bytes=5-2",
+            "bytes=-",
+            "bytes=",
+            "bytes=+1-2",
+            "bytes=a-b",
+            "0-1",
+        ] {
+            assert!(header.parse::<Range>().is_err(), "{}", header);
+        }
+        assert_eq!(
+            "items=0-1".parse::<Range>(),
+            Err(RangeParseError::UnsupportedUnit(String::from("items")))
+        );
+    }
+
+    #[test]
+    fn range_round_trips_through_display() {
+        let range: Range = "bytes=0-1,5-,-3".parse().unwrap();
+        assert_eq!(range.to_string(), "bytes=0-1, 5-, -3");
+        assert_eq!(range.to_string().parse::<Range>().unwrap(), range);
+    }
+
+    #[test]
+    fn unsatisfiable_range_is_answered_with_416() {
+        let res: Response = ranged("bytes=10-", b"0123456789");
+        let bytes: String = String::from_utf8(res.to_bytes()).unwrap();
+
+        assert_eq!(res.code(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert!(bytes.contains("Content-Range: bytes */10\r\n"), "{}", bytes);
+    }
+
+    #[test]
+    fn range_of_empty_body_is_answered_with_416() {
+        let res: Response = ranged("bytes=0-", b"");
+
+        assert_eq!(res.code(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[test]
+    fn satisfiable_range_is_answered_with_206() {
+        let res: Response = ranged("bytes=-4", b"0123456789");
+        let bytes: String = String::from_utf8(res.to_bytes()).unwrap();
+
+        assert_eq!(res.code(), StatusCode::PARTIAL_CONTENT);
+        assert!(
+            bytes.contains("Content-Range: bytes 6-9/10\r\n"),
+            "{}",
+            bytes
+        );
+        assert!(bytes.ends_with("\r\n\r\n6789"), "{}", bytes);
+    }
+
+    #[test]
+    fn content_range_parses_and_rejects_out_of_bounds() {
+        assert_eq!(
+            "bytes 0-4/10".parse::<ContentRange>(),
+            Ok(ContentRange::Bytes {
+                first: 0,
+                last: 4,
+                complete_length: Some(10)
+            })
+        );
+        assert_eq!(
+            "bytes */10".parse::<ContentRange>(),
+            Ok(ContentRange::Unsatisfied {
+                complete_length: 10
+            })
+        );
+        assert!("bytes 5-10/10".parse::<ContentRange>().is_err());
+        assert!("bytes */*".parse::<ContentRange>().is_err());
+    }
+}
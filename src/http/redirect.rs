@@ -0,0 +1,203 @@
+use super::Request;
+use super::percent::percent_encode_query_component;
+
+/// Builds a `Location` for a redirect from the request being answered.
+///
+/// Relative targets such as `../login` or `?page=2` are resolved against the
+/// request path the way a browser would, so handlers don't need to know
+/// where they are mounted.
+#[derive(Debug)]
+pub struct Location<'a> {
+    req: &'a Request,
+    target: String,
+    preserve_query: bool,
+    params: Vec<(String, String)>,
+    absolute: Option<bool>,
+}
+
+impl<'a> Location<'a> {
+    pub fn new(req: &'a Request, target: &str) -> Location<'a> {
+        Location {
+            req,
+            target: String::from(target),
+            preserve_query: false,
+            params: vec![],
+            absolute: None,
+        }
+    }
+
+    /// Carries the request's query parameters over to the target. Parameters
+    /// the target sets itself take precedence.
+    pub fn preserve_query(&mut self) -> &mut Self {
+        self.preserve_query = true;
+        self
+    }
+
+    /// Sets a query parameter on the target, replacing any existing value.
+    pub fn query_param(&mut self, key: &str, val: &str) -> &mut Self {
+        self.params.push((String::from(key), String::from(val)));
+        self
+    }
+
+    /// Makes the location an absolute URL built from the `Host` header.
+    /// With `trust_forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    /// are used instead when present; only set it for requests that came
+    /// through a trusted proxy, as clients can send those headers too.
+    pub fn absolute(&mut self, trust_forwarded: bool) -> &mut Self {
+        self.absolute = Some(trust_forwarded);
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let (target, fragment) = match self.target.split_once('#') {
+            Some((target, fragment)) => (target, Some(fragment)),
+            None => (self.target.as_str(), None),
+        };
+        let (target_path, target_query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        let (origin, path): (Option<String>, String) = match split_origin(target_path) {
+            Some((origin, path)) => (Some(String::from(origin)), String::from(path)),
+            None => (self.origin(), self.resolve_path(target_path)),
+        };
+
+        let mut location: String = origin.unwrap_or_default();
+        location.push_str(&path);
+
+        let query: String = self.query(target_query);
+        if !query.is_empty() {
+            location.push('?');
+            location.push_str(&query);
+        }
+
+        if let Some(fragment) = fragment {
+            location.push('#');
+            location.push_str(fragment);
+        }
+
+        location
+    }
+
+    /// The scheme and authority to prefix when an absolute URL is wanted.
+    fn origin(&self) -> Option<String> {
+        let trust_forwarded: bool = self.absolute?;
+        let forwarded = |name: &str| {
+            trust_forwarded
+                .then(|| self.req.header_val(name))
+                .flatten()
+                .and_then(|val| val.split(',').next())
+                .map(str::trim)
+                .filter(|val| !val.is_empty())
+        };
+
+        let proto: &str = forwarded("X-Forwarded-Proto").unwrap_or("http");
+        let host: &str = forwarded("X-Forwarded-Host")
+            .or_else(|| self.req.header_val("Host").map(String::as_str))?;
+
+        Some(format!("{}://{}", proto, host))
+    }
+
+    /// Resolves a target path against the request path (RFC 3986, section
+    /// 5.2), removing dot segments.
+    fn resolve_path(&self, target: &str) -> String {
+        let base: String = self.req.path.encoded();
+
+        let merged: String = if target.starts_with('/') {
+            String::from(target)
+        } else if target.is_empty() {
+            base
+        } else {
+            let dir: &str = base.rfind('/').map_or("/", |idx| &base[..=idx]);
+            format!("{}{}", dir, target)
+        };
+
+        remove_dot_segments(&merged)
+    }
+
+    fn query(&self, target_query: Option<&str>) -> String {
+        let mut pairs: Vec<(String, String)> = split_query(target_query.unwrap_or(""));
+
+        if self.preserve_query {
+            for (key, val) in split_query(self.req.query.as_deref().unwrap_or("")) {
+                if !pairs.iter().any(|(k, _)| *k == key) {
+                    pairs.push((key, val));
+                }
+            }
+        }
+
+        for (key, val) in &self.params {
+            let key: String = percent_encode_query_component(key);
+            let val: String = percent_encode_query_component(val);
+
+            pairs.retain(|(k, _)| *k != key);
+            pairs.push((key, val));
+        }
+
+        pairs
+            .iter()
+            .map(|(key, val)| match val.is_empty() {
+                true => key.clone(),
+                false => format!("{}={}", key, val),
+            })
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+}
+
+/// Splits `scheme://authority` or `//authority` off a target that has one.
+fn split_origin(target: &str) -> Option<(&str, &str)> {
+    let after_scheme: usize = match target.find("://") {
+        Some(idx) if is_scheme(&target[..idx]) => idx + 3,
+        _ if target.starts_with("//") => 2,
+        _ => return None,
+    };
+
+    let path_start: usize = target[after_scheme..]
+        .find('/')
+        .map_or(target.len(), |idx| after_scheme + idx);
+
+    Some((&target[..path_start], &target[path_start..]))
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut bytes = s.bytes();
+
+    bytes.next().is_some_and(|b| b.is_ascii_alphabetic())
+        && bytes.all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.'))
+}
+
+/// Still-encoded `key=value` pairs of a query string.
+fn split_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+            (String::from(key), String::from(val))
+        })
+        .collect()
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = vec![];
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+
+    for (i, part) in parts.iter().enumerate() {
+        match *part {
+            ".." => {
+                segments.pop();
+            }
+            "." => {}
+            part => segments.push(part),
+        }
+
+        // `a/b/..` resolves to `a/`, keeping the trailing slash.
+        if matches!(*part, "." | "..") && i + 1 == parts.len() {
+            segments.push("");
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
@@ -1,18 +1,45 @@
-use std::{collections::HashMap, error::Error, fmt, str::FromStr};
-
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 use tracing::instrument;
 
-use crate::{config::HttpProtocol, router::path::Path};
-
-use super::header::HttpHeader;
+use crate::{
+    base64,
+    config::{HttpProtocol, HttpProtocolParseError},
+    de,
+    flags::FeatureFlags,
+    router::path::Path,
+};
+
+use super::body;
+use super::etag::{ETagList, ETagParseError};
+use super::forwarded;
+use super::header::{self, Headers, HttpHeader};
+use super::media_type::{MediaType, MediaTypeParseError};
+use super::negotiate;
+use super::percent::{PercentDecodeError, percent_decode};
+use super::range::{Range, RangeParseError};
+use super::redirect::Location;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ReqType {
     Get,
     Post,
     Options,
     Connect,
+    /// Any other syntactically valid method token, e.g. `PURGE`.
+    Other(String),
 }
 
 impl FromStr for ReqType {
@@ -24,11 +51,49 @@ impl FromStr for ReqType {
             "POST" => Ok(Self::Post),
             "OPTIONS" => Ok(Self::Options),
             "CONNECT" => Ok(Self::Connect),
+            s if is_token(s) => Ok(Self::Other(String::from(s))),
             _ => Err(ReqTypeParseError),
         }
     }
 }
 
+impl fmt::Display for ReqType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Get => write!(f, "GET"),
+            Self::Post => write!(f, "POST"),
+            Self::Options => write!(f, "OPTIONS"),
+            Self::Connect => write!(f, "CONNECT"),
+            Self::Other(method) => write!(f, "{}", method),
+        }
+    }
+}
+
+/// Whether `s` is a `token` as defined by RFC 9110, section 5.6.2.
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes().all(|b| {
+            b.is_ascii_alphanumeric()
+                || matches!(
+                    b,
+                    b'!' | b'#'
+                        | b'$'
+                        | b'%'
+                        | b'&'
+                        | b'\''
+                        | b'*'
+                        | b'+'
+                        | b'-'
+                        | b'.'
+                        | b'^'
+                        | b'_'
+                        | b'`'
+                        | b'|'
+                        | b'~'
+                )
+        })
+}
+
 #[derive(Debug)]
 pub struct ReqTypeParseError;
 
@@ -40,9 +105,34 @@ impl fmt::Display for ReqTypeParseError {
 
 impl Error for ReqTypeParseError {}
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AcceptedEncoding {
     Gzip,
+    Identity,
+}
+
+impl AcceptedEncoding {
+    /// The encodings the server supports that an `Accept-Encoding` value
+    /// allows, most preferred first; gzip wins ties. `identity` is allowed
+    /// unless refused with `q=0`, either by name or through `*`.
+    pub fn negotiate(header: &str) -> Vec<AcceptedEncoding> {
+        let list: Vec<(String, f32)> = negotiate::parse_quality_list(header);
+        let identity_q: f32 = negotiate::quality_of(&list, "identity").unwrap_or(1.0);
+
+        let mut encodings: Vec<(AcceptedEncoding, f32)> = [
+            (
+                AcceptedEncoding::Gzip,
+                negotiate::quality_of(&list, "gzip").unwrap_or(0.0),
+            ),
+            (AcceptedEncoding::Identity, identity_q),
+        ]
+        .into_iter()
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+        encodings.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        encodings.into_iter().map(|(e, _)| e).collect()
+    }
 }
 
 impl HttpHeader for AcceptedEncoding {
@@ -59,6 +149,7 @@ impl fmt::Display for AcceptedEncoding {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             AcceptedEncoding::Gzip => write!(f, "gzip"),
+            AcceptedEncoding::Identity => write!(f, "identity"),
         }
     }
 }
@@ -69,6 +160,7 @@ impl FromStr for AcceptedEncoding {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "gzip" => Ok(AcceptedEncoding::Gzip),
+            "identity" => Ok(AcceptedEncoding::Identity),
             _ => Err(MessageEncodingParseError),
         }
     }
@@ -89,16 +181,58 @@ impl Error for MessageEncodingParseError {}
 pub struct Request {
     pub req_type: ReqType,
     pub path: Path,
+    pub query: Option<String>,
     pub protocol: HttpProtocol,
-    pub headers: HashMap<String, String>,
-    pub body: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Fields sent after a chunked body. They are kept apart from `headers`
+    /// as they arrive only once the head has been acted on.
+    pub trailers: Headers,
     pub accept_encodings: Vec<AcceptedEncoding>,
+    /// The address of the other end of the connection, when the request was
+    /// read from one.
+    pub peer_addr: Option<SocketAddr>,
+    client_addr: Option<IpAddr>,
+    tenant: Option<String>,
+    flags: Option<Arc<dyn FeatureFlags>>,
+    variants: Vec<(String, String)>,
+    /// Whether the response will set a new experiment subject cookie.
+    issues_variant_cookie: bool,
+    /// Set once a handler reads a variant, as its response then differs
+    /// between subjects.
+    variant_read: Arc<AtomicBool>,
 }
 
 impl Request {
-    #[instrument]
-    pub fn new(data: &str) -> Result<Request, RequestParseError> {
-        let split_data: Vec<&str> = data.split("\r\n").collect();
+    /// Parses a request from raw bytes. Only the request line and headers are
+    /// read as text; everything after the blank line is kept as the body.
+    #[instrument(skip(data))]
+    pub fn new(data: &[u8]) -> Result<Request, RequestParseError> {
+        Request::parse(data, false)
+    }
+
+    /// Like [`Request::new`], also accepting lines ending in a bare LF, runs
+    /// of spaces in the request line and whitespace before header colons,
+    /// as typed by hand.
+    #[instrument(skip(data))]
+    pub fn new_lenient(data: &[u8]) -> Result<Request, RequestParseError> {
+        Request::parse(data, true)
+    }
+
+    fn parse(data: &[u8], lenient: bool) -> Result<Request, RequestParseError> {
+        let (head, body): (&[u8], &[u8]) = match body::head_len(data, lenient) {
+            Some(len) => data.split_at(len),
+            None => (data, &[]),
+        };
+        let head: &str = std::str::from_utf8(head).map_err(|_| RequestParseError::NonTextHead)?;
+
+        let split_data: Vec<&str> = match lenient {
+            true => head
+                .split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                .collect(),
+            false => head.split("\r\n").collect(),
+        };
 
         if split_data.is_empty() {
             return Err(RequestParseError::EmptyMetadata);
@@ -106,76 +240,362 @@ impl Request {
 
         let req_info: &str = split_data[0];
 
-        let req_info_split: Vec<&str> = req_info.split(' ').collect();
+        let req_info_split: Vec<&str> = match lenient {
+            true => req_info.split_whitespace().collect(),
+            false => req_info.split(' ').collect(),
+        };
         if req_info_split.len() != 3 {
             return Err(RequestParseError::MalformedMetadata);
         }
 
+        // The version is checked first, as the rest of the line may not
+        // follow HTTP/1 syntax in other versions.
+        let req_protocol: HttpProtocol =
+            HttpProtocol::from_str(req_info_split[2]).map_err(|e| match e {
+                HttpProtocolParseError::UnsupportedVersion => {
+                    RequestParseError::UnsupportedVersion(String::from(req_info_split[2]))
+                }
+                e => RequestParseError::ProtocolParseError(e.to_string()),
+            })?;
+
         let req_type: ReqType = ReqType::from_str(req_info_split[0])
             .map_err(|e| RequestParseError::TypeParseError(e.to_string()))?;
-        let req_target: Path = Path::parse(
-            &extract_path_from_req_target(req_info_split[1])
-                .map_err(RequestParseError::TargetParseError)?,
-        )
-        .map_err(|e| RequestParseError::TargetParseError(e.to_string()))?;
-
-        let req_protocol: HttpProtocol = HttpProtocol::from_str(req_info_split[2])
-            .map_err(|e| RequestParseError::ProtocolParseError(e.to_string()))?;
-
-        let mut req_headers: HashMap<String, String> = HashMap::new();
+        let req_target: String = extract_path_from_req_target(req_info_split[1])
+            .map_err(RequestParseError::TargetParseError)?;
+        let (req_path, req_query) = match req_target.split_once('?') {
+            Some((path, query)) => (path, Some(String::from(query))),
+            None => (req_target.as_str(), None),
+        };
+        let req_path: Path = Path::parse_request(req_path)
+            .map_err(|e| RequestParseError::TargetParseError(e.to_string()))?;
 
-        let mut req_accept_encoding: Vec<AcceptedEncoding> = vec![];
+        let mut req_headers: Headers = Headers::with_capacity(split_data.len());
 
         for item in split_data.iter().take(split_data.len() - 2).skip(1) {
-            let header_data: Vec<&str> = item.split(": ").collect();
+            let (name, val) = header::parse_field(item, lenient)
+                .ok_or_else(|| RequestParseError::InvalidHeader(String::from(*item)))?;
 
-            if header_data.len() != 2 {
-                continue;
-            }
-
-            if header_data[0].eq_ignore_ascii_case("accept-encoding") {
-                let encodings = header_data[1]
-                    .split(",")
-                    .map(str::trim)
-                    .filter(|e| !e.is_empty());
-
-                for encoding in encodings {
-                    if let Ok(e) = AcceptedEncoding::from_str(encoding) {
-                        req_accept_encoding.push(e);
-                    }
-                }
-            }
-
-            req_headers.insert(header_data[0].to_lowercase(), String::from(header_data[1]));
+            req_headers.append(name, String::from(val));
         }
 
-        let body_split: Vec<&str> = data.split("\r\n\r\n").collect();
-        let req_body: String = if body_split.len() > 1 {
-            body_split[1..].join("\r\n\r\n")
-        } else {
-            String::from("")
+        let accept_encoding: Vec<&str> = req_headers
+            .get_all("Accept-Encoding")
+            .map(String::as_str)
+            .collect();
+        let req_accept_encoding: Vec<AcceptedEncoding> = match accept_encoding.is_empty() {
+            true => vec![],
+            false => AcceptedEncoding::negotiate(&accept_encoding.join(",")),
         };
 
         Ok(Request {
             req_type,
-            path: req_target,
+            path: req_path,
+            query: req_query,
             protocol: req_protocol,
             headers: req_headers,
-            body: req_body,
+            body: body.to_vec(),
+            trailers: Headers::new(),
             accept_encodings: req_accept_encoding,
+            peer_addr: None,
+            client_addr: None,
+            tenant: None,
+            flags: None,
+            variants: vec![],
+            issues_variant_cookie: false,
+            variant_read: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Records the connection the request came in on, resolving the client
+    /// from forwarding headers when `peer` is one of `trusted_proxies`.
+    pub(crate) fn set_peer(&mut self, peer: SocketAddr, trusted_proxies: &[IpAddr]) {
+        self.peer_addr = Some(peer);
+        self.client_addr = Some(forwarded::client_addr(
+            peer.ip(),
+            &self.headers,
+            trusted_proxies,
+        ));
+    }
+
+    /// The tenant the router resolved the request to, see
+    /// [`crate::router::Router::tenants`].
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub(crate) fn set_tenant(&mut self, id: &str) {
+        self.tenant = Some(String::from(id));
+    }
+
+    /// Whether `flag` is enabled for this request in `Config::feature_flags`.
+    /// Every flag is off when none are configured.
+    pub fn flag(&self, flag: &str) -> bool {
+        self.flags
+            .as_ref()
+            .is_some_and(|flags| flags.is_enabled(flag, self))
+    }
+
+    pub(crate) fn set_flags(&mut self, flags: Arc<dyn FeatureFlags>) {
+        self.flags = Some(flags);
+    }
+
+    /// The variant of `experiment` the request was assigned by
+    /// `Config::experiments`.
+    pub fn variant(&self, experiment: &str) -> Option<&str> {
+        self.variant_read.store(true, Ordering::Relaxed);
+
+        self.variants
+            .iter()
+            .find(|(name, _)| name == experiment)
+            .map(|(_, variant)| variant.as_str())
+    }
+
+    pub(crate) fn set_variants(&mut self, variants: Vec<(String, String)>, issues_cookie: bool) {
+        self.variants = variants;
+        self.issues_variant_cookie = issues_cookie;
+    }
+
+    /// A handle that tells, once the request has been handled, whether a
+    /// handler read a variant.
+    pub(crate) fn variant_read(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.variant_read)
+    }
+
+    /// Whether the response must not come from or go into a response cache:
+    /// it carries a new subject cookie, or differs between variants.
+    pub(crate) fn is_per_subject(&self) -> bool {
+        self.issues_variant_cookie || self.variant_read.load(Ordering::Relaxed)
+    }
+
+    /// The address of the client that made the request. Behind a trusted
+    /// proxy (`Config::trusted_proxies`) this is taken from `Forwarded` or
+    /// `X-Forwarded-For`; otherwise it is the peer. `None` for a request not
+    /// read from a connection.
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        self.client_addr
+    }
+
+    /// The body as UTF-8 text, if it is valid UTF-8.
+    pub fn body_text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    /// The charset from `supported` the client prefers according to its
+    /// `Accept-Charset` header, or `None` if it accepts none of them. Without
+    /// the header, any charset is acceptable and the first is picked.
+    pub fn preferred_charset<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        match self.header_val("Accept-Charset") {
+            Some(header) => {
+                negotiate::best_match(&negotiate::parse_quality_list(header), supported)
+            }
+            None => supported.first().copied(),
+        }
+    }
+
+    /// The parsed `Content-Type` header, if the request has one.
+    pub fn content_type(&self) -> Option<Result<MediaType, MediaTypeParseError>> {
+        self.header_val("Content-Type").map(|val| val.parse())
+    }
+
+    /// The parsed `Range` header, if the request has one.
+    pub fn range(&self) -> Option<Result<Range, RangeParseError>> {
+        self.header_val("Range").map(|range| range.parse())
+    }
+
+    /// The parsed `If-Match` header, if the request has one.
+    pub fn if_match(&self) -> Option<Result<ETagList, ETagParseError>> {
+        self.header_val("If-Match").map(|tags| tags.parse())
+    }
+
+    /// The parsed `If-None-Match` header, if the request has one.
+    pub fn if_none_match(&self) -> Option<Result<ETagList, ETagParseError>> {
+        self.header_val("If-None-Match").map(|tags| tags.parse())
+    }
+
+    /// Starts building a redirect `Location` resolved against this request.
+    pub fn redirect_to(&self, target: &str) -> Location<'_> {
+        Location::new(self, target)
+    }
+
+    /// The first value of the header, if the request has it.
     pub fn header_val(&self, header_key: &str) -> Option<&String> {
-        self.headers.get(header_key.to_lowercase().as_str())
+        self.headers.get(header_key)
+    }
+
+    /// Every value of the header, in the order the fields arrived.
+    pub fn header_all(&self, header_key: &str) -> Vec<&String> {
+        self.headers.get_all(header_key).collect()
+    }
+
+    /// The decoded `key=value` pairs of the query string, in order. `+` is
+    /// read as a space and a key without `=` gets an empty value.
+    pub fn query_pairs(&self) -> Result<Vec<(String, String)>, QueryError> {
+        let decode = |s: &str| percent_decode(&s.replace('+', " "));
+
+        self.query
+            .iter()
+            .flat_map(|query| query.split('&'))
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((decode(key)?, decode(val)?))
+            })
+            .collect()
+    }
+
+    /// Deserializes the query string into `T`. Values are parsed into the
+    /// field types, and keys repeated in the query fill `Vec` fields.
+    pub fn query_as<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        let pairs: Vec<(String, String)> = self.query_pairs()?;
+
+        de::from_pairs(pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .map_err(|e| QueryError::Deserialize(e.to_string()))
+    }
+
+    /// The pairs of the `Cookie` headers by name. Quoted values are unquoted,
+    /// and when a name repeats the first value is kept, as it is the one with
+    /// the most specific path.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let mut cookies: HashMap<String, String> = HashMap::new();
+
+        for header in self.headers.get_all("Cookie") {
+            for (name, val) in parse_cookie_pairs(header) {
+                cookies.entry(name).or_insert(val);
+            }
+        }
+
+        cookies
+    }
+
+    /// The user-id and password of `Authorization: Basic` credentials.
+    /// `None` if the header is absent, uses another scheme, or isn't valid
+    /// base64 of UTF-8 text containing a `:`.
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let credentials: &str = self.authorization("Basic")?;
+        let decoded: String = String::from_utf8(base64::decode(credentials)?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+
+        Some((String::from(user), String::from(password)))
+    }
+
+    /// The token of `Authorization: Bearer` credentials, if it is well formed.
+    pub fn bearer_token(&self) -> Option<&str> {
+        let token: &str = self.authorization("Bearer")?;
+        let body: &str = token.trim_end_matches('=');
+        let is_token68: bool = !body.is_empty()
+            && body
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b));
+
+        is_token68.then_some(token)
+    }
+
+    /// The credentials of the `Authorization` header when it uses `scheme`,
+    /// which is matched case-insensitively.
+    fn authorization(&self, scheme: &str) -> Option<&str> {
+        let (name, credentials) = self.header_val("Authorization")?.trim().split_once(' ')?;
+
+        match name.eq_ignore_ascii_case(scheme) {
+            true => Some(credentials.trim_start_matches(' ')),
+            false => None,
+        }
+    }
+
+    /// Deserializes the JSON body into `T`.
+    #[cfg(feature = "json")]
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        serde_json::from_slice(&self.body).map_err(JsonError::from)
     }
 
     pub fn has_connection_close_header(&self) -> bool {
-        if let Some(val) = self.header_val("Connection") {
-            return val == "close";
+        self.has_connection_option("close")
+    }
+
+    /// Whether the client wants the connection kept open after the response.
+    /// HTTP/1.1 connections persist unless the client sends
+    /// `Connection: close`; HTTP/1.0 ones only with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        match self.protocol {
+            HttpProtocol::Http10 => self.has_connection_option("keep-alive"),
+            HttpProtocol::Http11 => !self.has_connection_close_header(),
         }
+    }
 
-        false
+    fn has_connection_option(&self, option: &str) -> bool {
+        self.headers
+            .get_all("Connection")
+            .flat_map(|val| val.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(option))
+    }
+}
+
+/// Splits a `Cookie` header value into its `name=value` pairs. A `;` inside
+/// a quoted value doesn't end the pair, and pairs without a name are skipped.
+fn parse_cookie_pairs(header: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = vec![];
+    let mut rest: &str = header;
+
+    while !rest.is_empty() {
+        let (name, after_name) = match rest.split_once('=') {
+            Some((name, after_name)) if !name.contains(';') => (name.trim(), after_name),
+            _ => {
+                rest = rest.split_once(';').map_or("", |(_, after)| after);
+                continue;
+            }
+        };
+
+        let after_name: &str = after_name.trim_start();
+        let (val, after_val) = match after_name.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((val, after)) => (val, after.split_once(';').map_or("", |(_, a)| a)),
+                None => (quoted, ""),
+            },
+            None => match after_name.split_once(';') {
+                Some((val, after)) => (val.trim_end(), after),
+                None => (after_name.trim_end(), ""),
+            },
+        };
+
+        if !name.is_empty() {
+            pairs.push((String::from(name), String::from(val)));
+        }
+
+        rest = after_val;
+    }
+
+    pairs
+}
+
+#[derive(Error, Debug)]
+pub enum QueryError {
+    #[error("Invalid query string encoding: {0}")]
+    InvalidEncoding(#[from] PercentDecodeError),
+
+    #[error("Invalid query parameters: {0}")]
+    Deserialize(String),
+}
+
+#[cfg(feature = "json")]
+#[derive(Error, Debug)]
+pub enum JsonError {
+    #[error("Invalid JSON body: {0}")]
+    Syntax(String),
+
+    #[error("JSON body does not match the expected type: {0}")]
+    Data(String),
+
+    #[error("JSON body ended unexpectedly")]
+    Eof,
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for JsonError {
+    fn from(e: serde_json::Error) -> Self {
+        match e.classify() {
+            serde_json::error::Category::Data => JsonError::Data(e.to_string()),
+            serde_json::error::Category::Eof => JsonError::Eof,
+            _ => JsonError::Syntax(e.to_string()),
+        }
     }
 }
 
@@ -251,4 +671,122 @@ pub enum RequestParseError {
 
     #[error("Request protocol parse error: {0}")]
     ProtocolParseError(String),
+
+    #[error("Unsupported HTTP version: {0}")]
+    UnsupportedVersion(String),
+
+    #[error("Request line or headers are not valid UTF-8")]
+    NonTextHead,
+
+    #[error("Invalid header field: {0:?}")]
+    InvalidHeader(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(header: &str) -> Vec<(String, String)> {
+        parse_cookie_pairs(header)
+    }
+
+    fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, val)| (String::from(*name), String::from(*val)))
+            .collect()
+    }
+
+    fn with_auth(authorization: &str) -> Request {
+        let head: String = format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", authorization);
+
+        Request::new(head.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn cookie_pairs_are_split() {
+        assert_eq!(pairs("a=1; b=2"), owned(&[("a", "1"), ("b", "2")]));
+        assert_eq!(pairs(" a = 1 ;b=2;"), owned(&[("a", "1"), ("b", "2")]));
+        assert_eq!(pairs("a=; b=2"), owned(&[("a", ""), ("b", "2")]));
+    }
+
+    #[test]
+    fn empty_cookie_headers_have_no_pairs() {
+        assert_eq!(pairs(""), vec![]);
+        assert_eq!(pairs(";;"), vec![]);
+        assert_eq!(pairs("   "), vec![]);
+    }
+
+    #[test]
+    fn cookie_pairs_without_a_name_or_value_are_skipped() {
+        assert_eq!(pairs("=1; a=2"), owned(&[("a", "2")]));
+        assert_eq!(pairs("flag; a=2"), owned(&[("a", "2")]));
+        assert_eq!(pairs("a=1; flag"), owned(&[("a", "1")]));
+    }
+
+    #[test]
+    fn quoted_cookie_values_are_unquoted() {
+        assert_eq!(pairs(r#"a="x;y"; b=2"#), owned(&[("a", "x;y"), ("b", "2")]));
+        assert_eq!(pairs(r#"a="unterminated"#), owned(&[("a", "unterminated")]));
+    }
+
+    #[test]
+    fn basic_auth_decodes_credentials() {
+        // user:pa:ss
+        assert_eq!(
+            with_auth("Basic dXNlcjpwYTpzcw==").basic_auth(),
+            Some((String::from("user"), String::from("pa:ss")))
+        );
+        assert_eq!(
+            with_auth("basic Og==").basic_auth(),
+            Some((String::new(), String::new()))
+        );
+    }
+
+    #[test]
+    fn basic_auth_rejects_malformed_credentials() {
+        for authorization in [
+            "Basic",
+            "Basic ",
+            "Basic !!!!",
+            "Basic dXNlcg",
+            // "user", without a `:`
+            "Basic dXNlcg==",
+            // 0xff 0xfe, not UTF-8
+            "Basic //4=",
+            "Bearer dXNlcjpwYXNz",
+        ] {
+            assert_eq!(
+                with_auth(authorization).basic_auth(),
+                None,
+                "{:?}",
+                authorization
+            );
+        }
+    }
+
+    #[test]
+    fn bearer_token_must_be_token68() {
+        assert_eq!(
+            with_auth("Bearer abc.DEF-_~+/==").bearer_token(),
+            Some("abc.DEF-_~+/==")
+        );
+        assert_eq!(with_auth("bearer  abc").bearer_token(), Some("abc"));
+
+        for authorization in [
+            "Bearer",
+            "Bearer ",
+            "Bearer ===",
+            "Bearer a b",
+            "Bearer a=b",
+            "Basic abc",
+        ] {
+            assert_eq!(
+                with_auth(authorization).bearer_token(),
+                None,
+                "{:?}",
+                authorization
+            );
+        }
+    }
 }
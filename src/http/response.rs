@@ -1,57 +1,42 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fmt;
 use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
 
 use flate2::Compression;
 use flate2::write::GzEncoder;
 use tracing::info;
 
-use super::{AcceptedEncoding, Request, header::HttpHeader};
+use super::etag::{self, ETag};
+use super::media_type::MediaType;
+use super::range::{AcceptRanges, ContentRange};
+use super::sse::EventStream;
+use super::{
+    AcceptedEncoding, ReqType, Request, StatusCode,
+    header::{Headers, HttpHeader},
+};
 use crate::config::HttpProtocol;
-
-#[derive(Debug)]
-pub enum HttpResponseCode {
-    R200,
-    R201,
-    R400,
-    R404,
-}
-
-impl HttpResponseCode {
-    fn default_message(&self) -> &'static str {
-        match self {
-            HttpResponseCode::R200 => "OK",
-            HttpResponseCode::R201 => "Created",
-            HttpResponseCode::R400 => "Bad Request",
-            HttpResponseCode::R404 => "Not Found",
-        }
-    }
-}
-
-impl fmt::Display for HttpResponseCode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let text = match self {
-            HttpResponseCode::R200 => "200",
-            HttpResponseCode::R201 => "201",
-            HttpResponseCode::R400 => "400",
-            HttpResponseCode::R404 => "404",
-        };
-
-        write!(f, "{}", text)
-    }
-}
+use crate::quota::QuotaExceeded;
+use crate::router::fallible::HandlerError;
 
 #[derive(Debug)]
 pub enum ContentType {
     TextPlain,
+    TextEventStream,
     ApplicationOctectStream,
+    /// Any other media type, e.g. `image/x-icon`.
+    Media(Box<MediaType>),
 }
 
 impl fmt::Display for ContentType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ContentType::TextPlain => write!(f, "text/plain"),
+            ContentType::TextEventStream => write!(f, "text/event-stream"),
             ContentType::ApplicationOctectStream => write!(f, "application/octet-stream"),
+            ContentType::Media(media_type) => write!(f, "{}", media_type),
         }
     }
 }
@@ -75,6 +60,7 @@ impl ContentEcoding {
     fn from_accept_encoding(accept_encoding: &AcceptedEncoding) -> Option<Self> {
         match accept_encoding {
             AcceptedEncoding::Gzip => Some(ContentEcoding::Gzip),
+            AcceptedEncoding::Identity => None,
         }
     }
 }
@@ -100,51 +86,278 @@ impl fmt::Display for ContentEcoding {
 #[derive(Debug)]
 pub struct Response {
     protocol: HttpProtocol,
-    code: HttpResponseCode,
-    headers: HashMap<String, String>,
-    body: Option<String>,
+    code: StatusCode,
+    headers: Headers,
+    body: Option<Vec<u8>>,
     content_encoding: Option<ContentEcoding>,
     content_type: ContentType,
+    // `Arc<Vec<u8>>` and the box below keep `Response` small, as it is the
+    // error type of extractors.
+    serialized: Option<Arc<Vec<u8>>>,
+    handler_error: Option<Box<HandlerError>>,
+    hijack: Option<Hijack>,
+    progress: Option<ProgressCallback>,
+}
+
+/// How far along writing a response is.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// Bytes written so far, the head included.
+    pub written: usize,
+    /// Bytes the whole response takes.
+    pub total: usize,
+    /// Time since the write started.
+    pub elapsed: Duration,
+}
+
+pub(crate) struct ProgressCallback(Box<dyn FnMut(Progress) + Send + 'static>);
+
+impl ProgressCallback {
+    pub(crate) fn report(&mut self, progress: Progress) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Takes over a connection once a response head has been written to it.
+pub(crate) struct Hijack(Box<dyn FnOnce(&TcpStream) + Send + 'static>);
+
+impl Hijack {
+    pub(crate) fn run(self, stream: &TcpStream) {
+        (self.0)(stream)
+    }
+}
+
+impl fmt::Debug for Hijack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hijack")
+    }
 }
 
 impl Response {
     pub fn success() -> Response {
-        Response::default_message(HttpResponseCode::R200)
+        Response::default_message(StatusCode::OK)
     }
 
     pub fn bad_request() -> Response {
-        Response::default_message(HttpResponseCode::R400)
+        Response::default_message(StatusCode::BAD_REQUEST)
     }
 
     pub fn not_found() -> Response {
-        Response::default_message(HttpResponseCode::R404)
+        Response::default_message(StatusCode::NOT_FOUND)
+    }
+
+    pub fn not_acceptable() -> Response {
+        Response::default_message(StatusCode::NOT_ACCEPTABLE)
+    }
+
+    /// A 304 for a representation tagged `etag`.
+    pub fn not_modified(etag: &ETag) -> Response {
+        let mut res = Response::default_message(StatusCode::NOT_MODIFIED);
+        res.add_header("ETag", etag.to_string());
+
+        res
+    }
+
+    pub fn precondition_failed() -> Response {
+        Response::default_message(StatusCode::PRECONDITION_FAILED)
+    }
+
+    /// The 304 or 412 that the preconditions on `req` call for, given the
+    /// current representation is tagged `etag`, or `None` if the request
+    /// should be answered as usual.
+    pub fn conditional(req: &Request, etag: &ETag) -> Option<Response> {
+        match etag::evaluate_preconditions(req, Some(etag))? {
+            StatusCode::NOT_MODIFIED => Some(Response::not_modified(etag)),
+            _ => Some(Response::precondition_failed()),
+        }
+    }
+
+    pub fn request_timeout() -> Response {
+        let mut res = Response::default_message(StatusCode::REQUEST_TIMEOUT);
+        res.add_header("Connection", String::from("close"));
+
+        res
+    }
+
+    pub fn payload_too_large() -> Response {
+        let mut res = Response::default_message(StatusCode::CONTENT_TOO_LARGE);
+        res.add_header("Connection", String::from("close"));
+
+        res
+    }
+
+    pub fn uri_too_long() -> Response {
+        let mut res = Response::default_message(StatusCode::URI_TOO_LONG);
+        res.add_header("Connection", String::from("close"));
+
+        res
+    }
+
+    pub fn http_version_not_supported() -> Response {
+        let mut res = Response::default_message(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+        res.add_header("Connection", String::from("close"));
+
+        res
     }
 
-    pub fn default_message(code: HttpResponseCode) -> Response {
+    /// A 429 for a client that has used up `quota`, saying when it resets.
+    pub fn quota_exceeded(quota: &QuotaExceeded) -> Response {
+        let reset: String = quota.reset_after.as_secs().to_string();
+
+        let mut res = Response::default_message(StatusCode::TOO_MANY_REQUESTS);
+        res.add_header("X-Quota-Limit", quota.to_string());
+        res.add_header("X-Quota-Remaining", String::from("0"));
+        res.add_header("X-Quota-Reset", reset.clone());
+        res.add_header("Retry-After", reset);
+
+        res
+    }
+
+    pub fn too_many_requests() -> Response {
+        let mut res = Response::default_message(StatusCode::TOO_MANY_REQUESTS);
+        res.add_header("Connection", String::from("close"));
+
+        res
+    }
+
+    pub fn expectation_failed() -> Response {
+        let mut res = Response::default_message(StatusCode::EXPECTATION_FAILED);
+        res.add_header("Connection", String::from("close"));
+
+        res
+    }
+
+    pub fn request_header_fields_too_large() -> Response {
+        let mut res = Response::default_message(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        res.add_header("Connection", String::from("close"));
+
+        res
+    }
+
+    pub fn not_implemented() -> Response {
+        Response::default_message(StatusCode::NOT_IMPLEMENTED)
+    }
+
+    pub fn service_unavailable() -> Response {
+        Response::default_message(StatusCode::SERVICE_UNAVAILABLE)
+    }
+
+    /// A `200` whose head is sent without a `Content-Length`, after which `f`
+    /// has the raw connection to itself. The connection is closed once `f`
+    /// returns and is never reused.
+    pub fn hijack(f: impl FnOnce(&TcpStream) + Send + 'static) -> Response {
+        let mut res = Response::default_message(StatusCode::OK);
+        res.add_header("Connection", String::from("close"));
+        res.hijack = Some(Hijack(Box::new(f)));
+
+        res
+    }
+
+    /// A `text/event-stream` response whose events `f` sends through the
+    /// hijacked connection. The connection closes when `f` returns.
+    pub fn event_stream(f: impl FnOnce(EventStream) + Send + 'static) -> Response {
+        let mut res = Response::hijack(move |stream| f(EventStream::new(stream)));
+        res.content_type = ContentType::TextEventStream;
+        res.add_header("Cache-Control", String::from("no-cache"));
+
+        res
+    }
+
+    pub fn moved_permanently(location: &str) -> Response {
+        Response::redirect(StatusCode::MOVED_PERMANENTLY, location)
+    }
+
+    pub fn found(location: &str) -> Response {
+        Response::redirect(StatusCode::FOUND, location)
+    }
+
+    pub fn see_other(location: &str) -> Response {
+        Response::redirect(StatusCode::SEE_OTHER, location)
+    }
+
+    pub fn temporary_redirect(location: &str) -> Response {
+        Response::redirect(StatusCode::TEMPORARY_REDIRECT, location)
+    }
+
+    pub fn permanent_redirect(location: &str) -> Response {
+        Response::redirect(StatusCode::PERMANENT_REDIRECT, location)
+    }
+
+    fn redirect(code: StatusCode, location: &str) -> Response {
+        let mut res = Response::default_message(code);
+        res.add_header("Location", String::from(location));
+
+        res
+    }
+
+    pub fn method_not_allowed(allowed: &[&ReqType]) -> Response {
+        let mut res = Response::default_message(StatusCode::METHOD_NOT_ALLOWED);
+        let allowed: Vec<String> = allowed.iter().map(|r| r.to_string()).collect();
+        res.add_header("Allow", allowed.join(", "));
+
+        res
+    }
+
+    pub fn default_message(code: StatusCode) -> Response {
         Response {
             body: None,
             code,
             content_encoding: None,
-            headers: HashMap::new(),
+            headers: Headers::new(),
             protocol: HttpProtocol::Http11,
             content_type: ContentType::TextPlain,
+            serialized: None,
+            handler_error: None,
+            hijack: None,
+            progress: None,
         }
     }
 
     pub fn new(
         req: &Request,
-        code: HttpResponseCode,
+        code: StatusCode,
         body: Option<String>,
         content_type: ContentType,
         protocol: HttpProtocol,
     ) -> Response {
+        Response::from_bytes(
+            req,
+            code,
+            body.map(String::into_bytes),
+            content_type,
+            protocol,
+        )
+    }
+
+    /// Like [`Response::new`], for a body that need not be text.
+    pub fn from_bytes(
+        req: &Request,
+        code: StatusCode,
+        body: Option<Vec<u8>>,
+        content_type: ContentType,
+        protocol: HttpProtocol,
+    ) -> Response {
+        // Event streams are flushed event by event, which compression
+        // would buffer.
+        let compressible: bool = !matches!(content_type, ContentType::TextEventStream);
+
         let mut res = Response {
             protocol,
             code,
-            headers: HashMap::new(),
+            headers: Headers::new(),
             body,
             content_type,
-            content_encoding: if req.accept_encodings.is_empty() {
+            serialized: None,
+            handler_error: None,
+            hijack: None,
+            progress: None,
+            content_encoding: if req.accept_encodings.is_empty() || !compressible {
                 None
             } else {
                 ContentEcoding::from_accept_encoding(&req.accept_encodings[0])
@@ -152,58 +365,389 @@ impl Response {
         };
 
         if req.has_connection_close_header() {
-            res.add_header(String::from("Connection"), String::from("close"));
+            res.add_header("Connection", String::from("close"));
         }
 
         res
     }
 
+    /// Calls `f` as the response is written, after each chunk of it reaches
+    /// the connection, the last time with `written == total`. What a hijack
+    /// writes afterwards isn't reported, and neither are responses an
+    /// immutable route serves from its cache, as the handler doesn't run.
+    pub fn on_progress(mut self, f: impl FnMut(Progress) + Send + 'static) -> Response {
+        self.progress = Some(ProgressCallback(Box::new(f)));
+        self
+    }
+
+    /// Answers with the part of `content` selected by the request's `Range`
+    /// header: a 206 with `Content-Range`, or a 416 if nothing can be
+    /// selected. Multiple ranges are served as the one span covering them.
+    /// Without a valid `Range` header the whole content is sent with a 200.
+    /// The content is tagged with its digest, and `If-Match` and
+    /// `If-None-Match` are answered with 412 or 304 in place of any range.
+    pub fn ranged(req: &Request, content: Vec<u8>, content_type: ContentType) -> Response {
+        let etag: ETag = ETag::of(&content);
+        let len: u64 = content.len() as u64;
+        let ranges: Option<Vec<(u64, u64)>> = match req.range() {
+            Some(Ok(range)) => Some(range.resolve(len)),
+            _ => None,
+        };
+
+        let mut res: Response = match ranges {
+            None => Response::from_bytes(
+                req,
+                StatusCode::OK,
+                Some(content),
+                content_type,
+                req.protocol,
+            ),
+            Some(ranges) if ranges.is_empty() => {
+                let mut res = Response::default_message(StatusCode::RANGE_NOT_SATISFIABLE);
+                res.add_header(
+                    "Content-Range",
+                    ContentRange::Unsatisfied {
+                        complete_length: len,
+                    }
+                    .val(),
+                );
+
+                res
+            }
+            Some(ranges) => {
+                let first: u64 = ranges.iter().map(|(first, _)| *first).min().unwrap_or(0);
+                let last: u64 = ranges.iter().map(|(_, last)| *last).max().unwrap_or(0);
+
+                let mut res = Response::from_bytes(
+                    req,
+                    StatusCode::PARTIAL_CONTENT,
+                    Some(content[first as usize..=last as usize].to_vec()),
+                    content_type,
+                    req.protocol,
+                );
+                // A range applies to the representation as sent, so the part
+                // can't be compressed on the way out.
+                res.content_encoding = None;
+                res.add_header(
+                    "Content-Range",
+                    ContentRange::Bytes {
+                        first,
+                        last,
+                        complete_length: Some(len),
+                    }
+                    .val(),
+                );
+
+                res
+            }
+        };
+
+        res.add_header("Accept-Ranges", AcceptRanges::Bytes.val());
+
+        res.precondition(req, &etag).unwrap_or(res)
+    }
+
+    /// Answers in `protocol`, that of the request, in place of the HTTP/1.1
+    /// the response was built with. No effect once serialized.
+    pub(crate) fn set_protocol(&mut self, protocol: HttpProtocol) {
+        self.protocol = protocol;
+    }
+
+    /// Adds `Connection: keep-alive`, which HTTP/1.0 clients need to keep
+    /// the connection open. Returns false if the response can't take the
+    /// header: it was already serialized, hijacks the connection, or has a
+    /// `Connection` header of its own, which is left as it is.
+    pub(crate) fn keep_alive(&mut self) -> bool {
+        if self.serialized.is_some() || self.hijack.is_some() {
+            return false;
+        }
+
+        if let Some(connection) = self.headers.get("Connection") {
+            return connection.eq_ignore_ascii_case("keep-alive");
+        }
+
+        self.add_header("Connection", String::from("keep-alive"));
+        true
+    }
+
+    /// Writes the head and body with a single write, then flushes `writer`.
     pub fn write_to<W: std::io::Write>(&self, mut writer: W) -> std::io::Result<()> {
-        let (body_bytes, body_len) = match (&self.body, &self.content_encoding) {
+        writer.write_all(&self.bytes())?;
+        writer.flush()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if let Some(serialized) = &self.serialized {
+            return serialized.to_vec();
+        }
+
+        let body_bytes: Vec<u8> = if self.code.is_bodiless() {
+            Vec::new()
+        } else {
+            self.encoded_body()
+        };
+        let head: String = self.head(body_bytes.len());
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(head.len() + body_bytes.len());
+        bytes.extend_from_slice(head.as_bytes());
+        bytes.extend_from_slice(&body_bytes);
+
+        bytes
+    }
+
+    /// The serialized response, borrowed when it is already cached.
+    pub fn bytes(&self) -> Cow<'_, [u8]> {
+        match &self.serialized {
+            Some(serialized) => Cow::Borrowed(serialized.as_slice()),
+            None => Cow::Owned(self.to_bytes()),
+        }
+    }
+
+    pub(crate) fn from_serialized(code: StatusCode, serialized: Arc<Vec<u8>>) -> Response {
+        let mut res = Response::default_message(code);
+        res.serialized = Some(serialized);
+
+        res
+    }
+
+    /// Tags the response with `etag`, the tag of its uncompressed content,
+    /// and returns the 304 or 412 the preconditions on `req` call for
+    /// instead, if any.
+    pub(crate) fn precondition(&mut self, req: &Request, etag: &ETag) -> Option<Response> {
+        let etag: ETag = self.representation_etag(etag);
+
+        if self.serialized.is_none() {
+            self.add_header("ETag", etag.to_string());
+        }
+
+        Response::conditional(req, &etag)
+    }
+
+    /// The response's `ETag`, tagging a successful body with the digest of
+    /// its content first if the handler didn't set one.
+    pub(crate) fn etag_or_tag(&mut self) -> Option<ETag> {
+        if let Some(etag) = self.headers.get("ETag") {
+            return etag.parse().ok();
+        }
+
+        if self.serialized.is_some() || self.code != StatusCode::OK {
+            return None;
+        }
+
+        let etag: ETag = self.representation_etag(&ETag::of(self.body.as_deref()?));
+        self.add_header("ETag", etag.to_string());
+
+        Some(etag)
+    }
+
+    /// A gzipped body is a different representation from the content it
+    /// encodes, so its tag gets a `-gzip` suffix.
+    fn representation_etag(&self, etag: &ETag) -> ETag {
+        match self.content_encoding {
+            Some(ContentEcoding::Gzip) => ETag {
+                weak: etag.weak,
+                tag: format!("{}-gzip", etag.tag),
+            },
+            None => etag.clone(),
+        }
+    }
+
+    pub(crate) fn clone_serialized(&self) -> Response {
+        let serialized: Arc<Vec<u8>> = match &self.serialized {
+            Some(serialized) => Arc::clone(serialized),
+            None => Arc::new(self.to_bytes()),
+        };
+
+        Response::from_serialized(self.code, serialized)
+    }
+
+    /// A placeholder carrying a handler's error up to the router, which
+    /// replaces it with the mapped response.
+    pub(crate) fn from_handler_error(e: HandlerError) -> Response {
+        let mut res = Response::default_message(e.status());
+        res.handler_error = Some(Box::new(e));
+
+        res
+    }
+
+    pub(crate) fn take_handler_error(&mut self) -> Option<HandlerError> {
+        self.handler_error.take().map(|e| *e)
+    }
+
+    pub(crate) fn is_handler_error(&self) -> bool {
+        self.handler_error.is_some()
+    }
+
+    /// Whether compression and caching must leave the response alone: it
+    /// streams events or takes over the connection.
+    pub(crate) fn is_streaming(&self) -> bool {
+        self.hijack.is_some() || matches!(self.content_type, ContentType::TextEventStream)
+    }
+
+    /// Drops any content encoding and tells intermediaries not to transform
+    /// the response either.
+    pub(crate) fn no_transform(&mut self) {
+        self.content_encoding = None;
+
+        if self.serialized.is_none() {
+            self.add_cache_directive("no-transform");
+        }
+    }
+
+    pub(crate) fn take_progress(&mut self) -> Option<ProgressCallback> {
+        self.progress.take()
+    }
+
+    /// Removes the hijack, serializing the head first so that it is still
+    /// written without a `Content-Length`.
+    pub(crate) fn take_hijack(&mut self) -> Option<Hijack> {
+        if self.hijack.is_some() && self.serialized.is_none() {
+            self.serialized = Some(Arc::new(self.to_bytes()));
+        }
+
+        self.hijack.take()
+    }
+
+    /// Whether the response has a text body, which is always UTF-8.
+    pub(crate) fn is_text(&self) -> bool {
+        self.body.is_some() && matches!(self.content_type, ContentType::TextPlain)
+    }
+
+    pub fn code(&self) -> StatusCode {
+        self.code
+    }
+
+    fn encoded_body(&self) -> Vec<u8> {
+        match (&self.body, &self.content_encoding) {
             (Some(body), Some(ContentEcoding::Gzip)) => {
                 let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
 
-                if encoder.write_all(body.as_bytes()).is_err() {
-                    (body.as_bytes().to_vec(), body.len())
+                if encoder.write_all(body).is_err() {
+                    body.clone()
                 } else {
                     match encoder.finish() {
                         Ok(cmprsd_bytes) => {
-                            let n: usize = cmprsd_bytes.len();
                             info!(compressed_bytes = ?cmprsd_bytes);
-                            (cmprsd_bytes, n)
+                            cmprsd_bytes
                         }
-                        Err(_) => (body.as_bytes().to_vec(), body.len()),
+                        Err(_) => body.clone(),
                     }
                 }
             }
-            (Some(body), _) => (body.as_bytes().to_vec(), body.len()),
-            _ => (Vec::new(), 0),
-        };
+            (Some(body), _) => body.clone(),
+            _ => Vec::new(),
+        }
+    }
 
+    fn head(&self, body_len: usize) -> String {
         let mut lines: Vec<String> = vec![format!(
-            "HTTP/1.1 {} {}\r\n",
+            "{} {} {}\r\n",
+            self.protocol,
             self.code,
-            self.code.default_message()
+            self.code.canonical_reason().unwrap_or("")
         )];
 
-        self.headers.iter().for_each(|a| {
-            lines.push(format!("{}\r\n", [a.0.as_str(), a.1.as_str()].join(": ")));
+        self.headers.iter().for_each(|(key, val)| {
+            lines.push(format!("{}: {}\r\n", key, val));
         });
+        // 1xx, 204 and 304 responses end at the blank line, so they say
+        // nothing about content.
+        if self.code.is_bodiless() {
+            lines.push(String::from("\r\n"));
+            return lines.join("");
+        }
+
         lines.push(self.content_type.in_raw_http_form());
         if let Some(e) = &self.content_encoding {
             lines.push(e.in_raw_http_form());
         }
-        lines.push(format!("Content-Length: {}\r\n", body_len));
+        if self.hijack.is_none() {
+            lines.push(format!("Content-Length: {}\r\n", body_len));
+        }
 
         lines.push(String::from("\r\n"));
 
-        writer.write_all(lines.join("").as_bytes())?;
-        writer.write_all(&body_bytes)?;
+        lines.join("")
+    }
+
+    /// Marks a response that depends on the request's experiment variants
+    /// as private to the client, varying by the subject `cookie` if there is
+    /// one.
+    pub(crate) fn vary_by_variant(&mut self, cookie: Option<&str>) {
+        self.add_cache_directive("private");
+
+        if cookie.is_some() {
+            self.append_header("Vary", String::from("Cookie"));
+        }
+    }
 
-        Ok(())
+    /// Adds `directive` to `Cache-Control`, keeping the directives already
+    /// there, such as a handler's `no-store`.
+    fn add_cache_directive(&mut self, directive: &str) {
+        let val: String = match self.headers.get("Cache-Control") {
+            Some(existing)
+                if existing
+                    .split(',')
+                    .any(|d| d.trim().eq_ignore_ascii_case(directive)) =>
+            {
+                return;
+            }
+            Some(existing) if !existing.trim().is_empty() => {
+                format!("{}, {}", existing.trim(), directive)
+            }
+            _ => String::from(directive),
+        };
+
+        self.add_header("Cache-Control", val);
     }
 
-    fn add_header(&mut self, key: String, val: String) {
+    pub(crate) fn add_header(&mut self, key: &str, val: String) {
         self.headers.insert(key, val);
     }
+
+    /// Adds a value for `key` alongside any already set, as for
+    /// `Set-Cookie`.
+    pub(crate) fn append_header(&mut self, key: &str, val: String) {
+        self.headers.append(key, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_control(res: &Response) -> Option<&str> {
+        res.headers.get("Cache-Control").map(String::as_str)
+    }
+
+    #[test]
+    fn vary_by_variant_keeps_handler_cache_control() {
+        let mut res: Response = Response::success();
+        res.add_header("Cache-Control", String::from("no-store"));
+        res.vary_by_variant(Some("exp"));
+
+        assert_eq!(cache_control(&res), Some("no-store, private"));
+        assert_eq!(res.headers.get("Vary").map(String::as_str), Some("Cookie"));
+    }
+
+    #[test]
+    fn vary_by_variant_adds_private_once() {
+        let mut res: Response = Response::success();
+        res.vary_by_variant(None);
+        assert_eq!(cache_control(&res), Some("private"));
+
+        res.add_header("Cache-Control", String::from("Private, max-age=60"));
+        res.vary_by_variant(None);
+        assert_eq!(cache_control(&res), Some("Private, max-age=60"));
+        assert!(!res.headers.contains("Vary"));
+    }
+
+    #[test]
+    fn no_transform_keeps_existing_directives() {
+        let mut res: Response = Response::success();
+        res.add_header("Cache-Control", String::from("no-cache"));
+        res.no_transform();
+
+        assert_eq!(cache_control(&res), Some("no-cache, no-transform"));
+    }
 }
@@ -0,0 +1,44 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+
+/// Writes server-sent events to a connection taken over by
+/// [`Response::event_stream`](super::Response::event_stream). Every event is
+/// flushed as soon as it is sent.
+#[derive(Debug)]
+pub struct EventStream<'a> {
+    stream: &'a TcpStream,
+}
+
+impl<'a> EventStream<'a> {
+    pub(crate) fn new(stream: &'a TcpStream) -> EventStream<'a> {
+        EventStream { stream }
+    }
+
+    /// Sends `data` as one event, optionally named `event`. Each line of
+    /// `data` becomes its own `data:` field.
+    pub fn send(&mut self, event: Option<&str>, data: &str) -> io::Result<()> {
+        let mut frame: String = String::new();
+
+        if let Some(event) = event {
+            frame.push_str(&format!("event: {}\n", event));
+        }
+
+        for line in data.split('\n') {
+            frame.push_str(&format!("data: {}\n", line));
+        }
+        frame.push('\n');
+
+        self.write(frame.as_bytes())
+    }
+
+    /// Sends a comment line, which clients ignore. Useful to keep idle
+    /// connections from being closed by intermediaries.
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        self.write(format!(": {}\n\n", text).as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes)?;
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt;
+
+use thiserror::Error;
+
+/// A response status code. Any three-digit code can be sent; the ones in
+/// the IANA registry have named constants and reason phrases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+macro_rules! status_codes {
+    ($($name:ident = $code:literal, $reason:literal;)*) => {
+        impl StatusCode {
+            $(pub const $name: StatusCode = StatusCode($code);)*
+
+            /// The reason phrase registered for the code, e.g. `Not Found`.
+            pub fn canonical_reason(&self) -> Option<&'static str> {
+                match self.0 {
+                    $($code => Some($reason),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    CONTINUE = 100, "Continue";
+    SWITCHING_PROTOCOLS = 101, "Switching Protocols";
+    PROCESSING = 102, "Processing";
+    EARLY_HINTS = 103, "Early Hints";
+
+    OK = 200, "OK";
+    CREATED = 201, "Created";
+    ACCEPTED = 202, "Accepted";
+    NON_AUTHORITATIVE_INFORMATION = 203, "Non-Authoritative Information";
+    NO_CONTENT = 204, "No Content";
+    RESET_CONTENT = 205, "Reset Content";
+    PARTIAL_CONTENT = 206, "Partial Content";
+    MULTI_STATUS = 207, "Multi-Status";
+    ALREADY_REPORTED = 208, "Already Reported";
+    IM_USED = 226, "IM Used";
+
+    MULTIPLE_CHOICES = 300, "Multiple Choices";
+    MOVED_PERMANENTLY = 301, "Moved Permanently";
+    FOUND = 302, "Found";
+    SEE_OTHER = 303, "See Other";
+    NOT_MODIFIED = 304, "Not Modified";
+    USE_PROXY = 305, "Use Proxy";
+    TEMPORARY_REDIRECT = 307, "Temporary Redirect";
+    PERMANENT_REDIRECT = 308, "Permanent Redirect";
+
+    BAD_REQUEST = 400, "Bad Request";
+    UNAUTHORIZED = 401, "Unauthorized";
+    PAYMENT_REQUIRED = 402, "Payment Required";
+    FORBIDDEN = 403, "Forbidden";
+    NOT_FOUND = 404, "Not Found";
+    METHOD_NOT_ALLOWED = 405, "Method Not Allowed";
+    NOT_ACCEPTABLE = 406, "Not Acceptable";
+    PROXY_AUTHENTICATION_REQUIRED = 407, "Proxy Authentication Required";
+    REQUEST_TIMEOUT = 408, "Request Timeout";
+    CONFLICT = 409, "Conflict";
+    GONE = 410, "Gone";
+    LENGTH_REQUIRED = 411, "Length Required";
+    PRECONDITION_FAILED = 412, "Precondition Failed";
+    CONTENT_TOO_LARGE = 413, "Content Too Large";
+    URI_TOO_LONG = 414, "URI Too Long";
+    UNSUPPORTED_MEDIA_TYPE = 415, "Unsupported Media Type";
+    RANGE_NOT_SATISFIABLE = 416, "Range Not Satisfiable";
+    EXPECTATION_FAILED = 417, "Expectation Failed";
+    MISDIRECTED_REQUEST = 421, "Misdirected Request";
+    UNPROCESSABLE_CONTENT = 422, "Unprocessable Content";
+    LOCKED = 423, "Locked";
+    FAILED_DEPENDENCY = 424, "Failed Dependency";
+    TOO_EARLY = 425, "Too Early";
+    UPGRADE_REQUIRED = 426, "Upgrade Required";
+    PRECONDITION_REQUIRED = 428, "Precondition Required";
+    TOO_MANY_REQUESTS = 429, "Too Many Requests";
+    REQUEST_HEADER_FIELDS_TOO_LARGE = 431, "Request Header Fields Too Large";
+    UNAVAILABLE_FOR_LEGAL_REASONS = 451, "Unavailable For Legal Reasons";
+
+    INTERNAL_SERVER_ERROR = 500, "Internal Server Error";
+    NOT_IMPLEMENTED = 501, "Not Implemented";
+    BAD_GATEWAY = 502, "Bad Gateway";
+    SERVICE_UNAVAILABLE = 503, "Service Unavailable";
+    GATEWAY_TIMEOUT = 504, "Gateway Timeout";
+    HTTP_VERSION_NOT_SUPPORTED = 505, "HTTP Version Not Supported";
+    VARIANT_ALSO_NEGOTIATES = 506, "Variant Also Negotiates";
+    INSUFFICIENT_STORAGE = 507, "Insufficient Storage";
+    LOOP_DETECTED = 508, "Loop Detected";
+    NOT_EXTENDED = 510, "Not Extended";
+    NETWORK_AUTHENTICATION_REQUIRED = 511, "Network Authentication Required";
+}
+
+impl StatusCode {
+    /// Fails for anything outside 100 to 999.
+    pub fn from_u16(code: u16) -> Result<StatusCode, InvalidStatusCode> {
+        match code {
+            100..=999 => Ok(StatusCode(code)),
+            code => Err(InvalidStatusCode(code)),
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.0)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.0)
+    }
+
+    /// Whether responses with this code end at their head, never carrying
+    /// content or a `Content-Length`.
+    pub(crate) fn is_bodiless(&self) -> bool {
+        self.is_informational() || self.0 == 204 || self.0 == 304
+    }
+}
+
+impl TryFrom<u16> for StatusCode {
+    type Error = InvalidStatusCode;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        StatusCode::from_u16(code)
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(code: StatusCode) -> Self {
+        code.0
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("`{0}` is not a three-digit status code")]
+pub struct InvalidStatusCode(u16);
@@ -0,0 +1,28 @@
+pub mod access_log;
+mod affinity;
+#[cfg(feature = "alloc-tracking")]
+pub mod alloc_tracking;
+mod base64;
+pub mod clock;
+pub mod config;
+mod conn_limit;
+mod de;
+pub mod events;
+pub mod experiment;
+pub mod flags;
+pub mod http;
+mod net;
+pub mod quota;
+pub mod router;
+pub mod rsttp_server;
+mod sha256;
+pub mod subsystem;
+mod thread_pool;
+pub mod transfer;
+pub mod upload;
+pub mod watchdog;
+
+/// Compiles the README's usage example.
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+struct ReadmeDoctests;
@@ -1,27 +1,30 @@
+use std::io::Read;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use std::{env, fs};
-
-use config::Config;
-use http::{ContentType, HttpResponseCode, Response};
-use router::Router;
-use router::path::PathParseError;
-use rsttp_server::RsttpServer;
-
-mod config;
-mod http;
-mod router;
-mod rsttp_server;
-mod thread_pool;
-
-fn setup_routes(router: &mut Router<AppContext>) -> Result<(), PathParseError> {
-    router.get("/", |_req, _, _| Response::success())?;
+use std::{env, fs, process};
+
+use rsttp::config::Config;
+use rsttp::http::{ContentType, Response, StatusCode};
+use rsttp::router::fallible::{HandlerError, fallible};
+use rsttp::router::params::PathParams;
+use rsttp::router::{RouteError, Router};
+use rsttp::rsttp_server::RsttpServer;
+use rsttp::upload::{
+    ContentStore, Durability, ScanVerdict, SniffPolicy, Upload, UploadProcessors, UploadScanner,
+    is_portable_file_name, write_atomically,
+};
+
+fn setup_routes(router: &mut Router<AppContext>) -> Result<(), RouteError> {
+    router
+        .get("/", |_req, _, _| Response::success())?
+        .immutable();
 
     router.get("/user-agent", |req, _, _| {
         match req.header_val("User-Agent") {
             Some(header_val) => Response::new(
                 req,
-                HttpResponseCode::R200,
+                StatusCode::OK,
                 Some(header_val.clone()),
                 ContentType::TextPlain,
                 req.protocol,
@@ -31,11 +34,11 @@ fn setup_routes(router: &mut Router<AppContext>) -> Result<(), PathParseError> {
     })?;
 
     router.get("/echo/:text", |req, params, _| {
-        if let Some(text) = get_param!(params, "text") {
+        if let Some(text) = params.get("text") {
             Response::new(
                 req,
-                HttpResponseCode::R200,
-                Some(text),
+                StatusCode::OK,
+                Some(String::from(text)),
                 ContentType::TextPlain,
                 req.protocol,
             )
@@ -44,57 +47,122 @@ fn setup_routes(router: &mut Router<AppContext>) -> Result<(), PathParseError> {
         }
     })?;
 
-    router.get("/files/:path", |req, params, ctx| {
-        if let Some(path) = get_param!(params, "path") {
-            let file_path: String = format!("{}/{}", ctx.static_files_dir, path);
-            let file_content = fs::read_to_string(file_path);
-            match file_content {
-                Ok(content) => Response::new(
-                    req,
-                    HttpResponseCode::R200,
-                    Some(content),
-                    ContentType::ApplicationOctectStream,
-                    req.protocol,
-                ),
-                Err(_) => Response::not_found(),
-            }
-        } else {
-            Response::bad_request()
-        }
-    })?;
+    router.get(
+        "/files/:path",
+        fallible(|req, params, ctx: &AppContext| {
+            let path: String = file_name_param(&params)?;
+            let content: Vec<u8> = fs::read(Path::new(&ctx.static_files_dir).join(path))?;
+
+            Ok(Response::ranged(
+                req,
+                content,
+                ContentType::ApplicationOctectStream,
+            ))
+        }),
+    )?;
+
+    router.post(
+        "/files/:path",
+        fallible(|req, params, ctx: &AppContext| {
+            let path: String = file_name_param(&params)?;
+            let mut upload: Upload = Upload::from_request(req, &path);
+            ctx.uploads.process(&mut upload)?;
 
-    router.post("/files/:path", |req, params, ctx| {
-        if let Some(path) = get_param!(params, "path") {
-            let file_path: String = format!("{}/{}", ctx.static_files_dir, path);
             let _: Result<(), std::io::Error> = fs::create_dir_all(&ctx.static_files_dir);
+            write_atomically(
+                Path::new(&ctx.static_files_dir),
+                &upload.file_name,
+                upload.body,
+                Durability::File,
+            )?;
+
+            Ok(Response::new(
+                req,
+                StatusCode::CREATED,
+                None,
+                ContentType::ApplicationOctectStream,
+                req.protocol,
+            ))
+        }),
+    )?;
 
-            let is_file_written: Result<(), std::io::Error> = fs::write(file_path, &req.body);
-            match is_file_written {
-                Ok(_) => Response::new(
-                    req,
-                    HttpResponseCode::R201,
-                    None,
-                    ContentType::ApplicationOctectStream,
-                    req.protocol,
-                ),
-                Err(_) => Response::not_found(),
-            }
-        } else {
-            Response::bad_request()
-        }
-    })?;
+    router.post(
+        "/blobs",
+        fallible(|req, _, ctx: &AppContext| {
+            let digest: String = ctx.blobs.put(&req.body)?;
+
+            Ok(Response::new(
+                req,
+                StatusCode::CREATED,
+                Some(digest),
+                ContentType::TextPlain,
+                req.protocol,
+            ))
+        }),
+    )?;
+
+    router.get(
+        "/blobs/:digest",
+        fallible(|req, params, ctx: &AppContext| {
+            let digest: String = params.get_as("digest")?;
+
+            Ok(Response::from_bytes(
+                req,
+                StatusCode::OK,
+                Some(ctx.blobs.get(&digest)?),
+                ContentType::ApplicationOctectStream,
+                req.protocol,
+            ))
+        }),
+    )?;
+
+    router.method(
+        "DELETE",
+        "/blobs/:digest",
+        fallible(|_, params, ctx: &AppContext| {
+            ctx.blobs.release(&params.get_as::<String>("digest")?)?;
+
+            Ok(Response::success())
+        }),
+    )?;
 
     Ok(())
 }
 
-#[macro_export]
-macro_rules! get_param {
-    ( $opts:expr, $key:expr ) => {{ $opts.as_ref().and_then(|m| m.get($key)).cloned() }};
+fn file_name_param(params: &PathParams) -> Result<String, HandlerError> {
+    match params.get("path") {
+        Some(path) if is_portable_file_name(path) => Ok(String::from(path)),
+        _ => Err(HandlerError::BadRequest(String::from("invalid file name"))),
+    }
 }
 
+/// Rejects uploads carrying the EICAR antivirus test signature.
+struct EicarScanner;
+
+impl UploadScanner for EicarScanner {
+    fn scan(&self, _upload: &Upload, body: &mut dyn Read) -> std::io::Result<ScanVerdict> {
+        const SIGNATURE: &[u8] = b"EICAR-STANDARD-ANTIVIRUS-TEST-FILE";
+
+        let mut content: Vec<u8> = Vec::new();
+        body.read_to_end(&mut content)?;
+
+        if content.windows(SIGNATURE.len()).any(|w| w == SIGNATURE) {
+            return Ok(ScanVerdict::Rejected(String::from("EICAR test signature")));
+        }
+
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+#[cfg(feature = "alloc-tracking")]
+#[global_allocator]
+static GLOBAL: rsttp::alloc_tracking::CountingAllocator = rsttp::alloc_tracking::CountingAllocator;
+
 #[derive(Debug)]
 struct AppContext {
     static_files_dir: String,
+    uploads: UploadProcessors,
+    blobs: ContentStore,
 }
 
 fn main() {
@@ -109,19 +177,38 @@ fn main() {
         default_file_dir
     };
 
+    let mut uploads: UploadProcessors = UploadProcessors::new();
+    uploads
+        .sniff(SniffPolicy::Reject)
+        .scanner(EicarScanner)
+        .on_type("text/*", |upload| match std::str::from_utf8(upload.body) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(HandlerError::BadRequest(String::from("text is not UTF-8"))),
+        })
+        .on_type("*/*", |_| Ok(()));
+
     let ctx: AppContext = AppContext {
+        blobs: ContentStore::new(Path::new(&files_dir).join(".blobs"), Durability::File),
         static_files_dir: files_dir,
+        uploads,
     };
 
     let config: Config<AppContext> = Config {
-        port: 4221,
-        ctx,
         persist_connection_for: Duration::from_secs(5),
+        ..Config::new(4221, ctx)
     };
 
+    if let Err(e) = config.validate() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+
     let mut router: Router<AppContext> = Router::new();
 
-    let _ = setup_routes(&mut router);
+    if let Err(e) = setup_routes(&mut router) {
+        eprintln!("Error: Failed to define routes: {}", e);
+        process::exit(1);
+    }
 
     let server: RsttpServer<AppContext> = RsttpServer::new(config, router, 8);
 
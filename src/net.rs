@@ -0,0 +1,106 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::OnceLock;
+
+/// Binds a listener with `SO_REUSEPORT` set, so several listeners on the same
+/// address can have incoming connections balanced between them by the kernel.
+#[cfg(unix)]
+pub fn bind_reuseport(addr: SocketAddr) -> io::Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    // SAFETY: plain socket(2) call; the returned descriptor is checked below.
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: `fd` is an open socket owned by nobody else; the listener takes
+    // ownership and closes it on drop, including on the error paths below.
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    let enable: libc::c_int = 1;
+    for opt in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        // SAFETY: `enable` outlives the call and its size is passed alongside.
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                opt,
+                &enable as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let res = match addr {
+        SocketAddr::V4(v4) => {
+            // SAFETY: all-zeroes is a valid sockaddr_in.
+            let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+
+            // SAFETY: `sin` is a valid address of the given size.
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(v6) => {
+            // SAFETY: all-zeroes is a valid sockaddr_in6.
+            let mut sin6: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_scope_id = v6.scope_id();
+
+            // SAFETY: `sin6` is a valid address of the given size.
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sin6 as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: listen(2) on a bound socket.
+    if unsafe { libc::listen(fd, 1024) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+pub fn bind_reuseport(addr: SocketAddr) -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("SO_REUSEPORT is not available to bind {}", addr),
+    ))
+}
+
+/// Whether [`bind_reuseport`] works here, found by binding a loopback
+/// listener once rather than trusting the target, since kernels and
+/// sandboxes can lack the option on Unix too.
+pub fn reuseport_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+    *AVAILABLE.get_or_init(|| bind_reuseport(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).is_ok())
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::http::Request;
+use crate::transfer::{ClientIdentity, Transfer};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// The calendar period, in UTC, a quota is reset after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    /// The start and end, in seconds since the Unix epoch, of the period
+    /// `now` falls in.
+    fn bounds(&self, now: SystemTime) -> (u64, u64) {
+        let secs: u64 = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let day: u64 = secs / SECS_PER_DAY;
+
+        match self {
+            QuotaPeriod::Daily => (day * SECS_PER_DAY, (day + 1) * SECS_PER_DAY),
+            QuotaPeriod::Monthly => {
+                let (year, month, _) = civil_from_days(day);
+                let (next_year, next_month) = match month {
+                    12 => (year + 1, 1),
+                    month => (year, month + 1),
+                };
+
+                (
+                    days_from_civil(year, month) * SECS_PER_DAY,
+                    days_from_civil(next_year, next_month) * SECS_PER_DAY,
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for QuotaPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaPeriod::Daily => write!(f, "day"),
+            QuotaPeriod::Monthly => write!(f, "month"),
+        }
+    }
+}
+
+/// What a client may use per period. A request is refused once either
+/// count has been reached, so the request that crosses the byte limit is
+/// still served.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaLimit {
+    pub period: QuotaPeriod,
+    pub max_requests: Option<u64>,
+    /// Bytes received and sent together, as [`Transfer::bytes`] counts them.
+    pub max_bytes: Option<u64>,
+}
+
+impl QuotaLimit {
+    /// Whether `used` has reached this limit, so a request would be
+    /// refused.
+    pub fn is_reached(&self, used: &Transfer) -> bool {
+        self.reached(used).is_some()
+    }
+
+    /// The count reached by `used`, and whether it is the byte count.
+    fn reached(&self, used: &Transfer) -> Option<(u64, bool)> {
+        match (self.max_requests, self.max_bytes) {
+            (Some(max), _) if used.requests >= max => Some((max, false)),
+            (_, Some(max)) if used.bytes() >= max => Some((max, true)),
+            _ => None,
+        }
+    }
+}
+
+/// Where quota usage is kept, e.g. a database shared by several servers so
+/// usage survives restarts. Each period is identified by its start, in
+/// seconds since the Unix epoch.
+pub trait QuotaStore: Send + Sync {
+    /// Usage by `client` in the period starting at `period_start`.
+    fn usage(&self, client: &str, period: QuotaPeriod, period_start: u64) -> Transfer;
+
+    /// Adds `usage` to what `client` used in the period starting at
+    /// `period_start`.
+    fn record(&self, client: &str, period: QuotaPeriod, period_start: u64, usage: Transfer);
+
+    /// Counts one request by `client` in the period of each of `limits`,
+    /// given with the start of that period, unless one of them has been
+    /// reached. Then nothing is counted, and the index of that limit is
+    /// returned with the usage that reached it.
+    ///
+    /// The default reads [`QuotaStore::usage`] and then records, so
+    /// requests racing each other can overshoot a limit; stores should
+    /// check and count in one step, as [`MemoryQuotaStore`] does.
+    fn try_consume(
+        &self,
+        client: &str,
+        limits: &[(QuotaLimit, u64)],
+    ) -> Result<(), (usize, Transfer)> {
+        for (i, (limit, start)) in limits.iter().enumerate() {
+            let used: Transfer = self.usage(client, limit.period, *start);
+            if limit.is_reached(&used) {
+                return Err((i, used));
+            }
+        }
+
+        for (period, start) in periods_of(limits) {
+            self.record(client, period, start, Transfer::request(0, 0));
+        }
+
+        Ok(())
+    }
+
+    /// Writes out any usage held back, such as batched writes. Called when
+    /// the server stops.
+    fn flush(&self) {}
+}
+
+/// The distinct periods, with their starts, that `limits` are counted in.
+fn periods_of(limits: &[(QuotaLimit, u64)]) -> Vec<(QuotaPeriod, u64)> {
+    let mut periods: Vec<(QuotaPeriod, u64)> = vec![];
+    for (limit, start) in limits {
+        if !periods.contains(&(limit.period, *start)) {
+            periods.push((limit.period, *start));
+        }
+    }
+
+    periods
+}
+
+/// Keeps usage in memory, for the current period of each client only.
+/// Usage from earlier periods is dropped once a later one starts.
+#[derive(Debug, Default)]
+pub struct MemoryQuotaStore {
+    usage: Mutex<MemoryUsage>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryUsage {
+    /// The start of the latest period seen, by period.
+    current: HashMap<QuotaPeriod, u64>,
+    clients: HashMap<(String, QuotaPeriod), Transfer>,
+}
+
+impl MemoryUsage {
+    /// Moves `period` on to the one starting at `period_start`, if that is
+    /// later, dropping all usage from the one before. Returns whether
+    /// `period_start` is the current period, rather than an earlier one.
+    fn advance(&mut self, period: QuotaPeriod, period_start: u64) -> bool {
+        let current: &mut u64 = self.current.entry(period).or_insert(period_start);
+
+        if period_start > *current {
+            *current = period_start;
+            self.clients.retain(|(_, p), _| *p != period);
+        }
+
+        period_start == *current
+    }
+
+    fn used(&self, client: &str, period: QuotaPeriod) -> Transfer {
+        self.clients
+            .get(&(String::from(client), period))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn add(&mut self, client: &str, period: QuotaPeriod, usage: Transfer) {
+        *self
+            .clients
+            .entry((String::from(client), period))
+            .or_default() += usage;
+    }
+}
+
+impl QuotaStore for MemoryQuotaStore {
+    fn usage(&self, client: &str, period: QuotaPeriod, period_start: u64) -> Transfer {
+        let Ok(mut usage) = self.usage.lock() else {
+            return Transfer::default();
+        };
+
+        match usage.advance(period, period_start) {
+            true => usage.used(client, period),
+            false => Transfer::default(),
+        }
+    }
+
+    fn record(&self, client: &str, period: QuotaPeriod, period_start: u64, usage: Transfer) {
+        let Ok(mut all) = self.usage.lock() else {
+            return;
+        };
+
+        if all.advance(period, period_start) {
+            all.add(client, period, usage);
+        }
+    }
+
+    fn try_consume(
+        &self,
+        client: &str,
+        limits: &[(QuotaLimit, u64)],
+    ) -> Result<(), (usize, Transfer)> {
+        let Ok(mut all) = self.usage.lock() else {
+            return Ok(());
+        };
+
+        for (i, (limit, start)) in limits.iter().enumerate() {
+            let used: Transfer = match all.advance(limit.period, *start) {
+                true => all.used(client, limit.period),
+                false => Transfer::default(),
+            };
+            if limit.is_reached(&used) {
+                return Err((i, used));
+            }
+        }
+
+        for (period, start) in periods_of(limits) {
+            if all.advance(period, start) {
+                all.add(client, period, Transfer::request(0, 0));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A quota a request was refused for.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaExceeded {
+    pub period: QuotaPeriod,
+    /// The count that was reached: requests, or bytes with `bytes`.
+    pub limit: u64,
+    pub bytes: bool,
+    /// Time until the period ends and the quota is reset.
+    pub reset_after: Duration,
+}
+
+/// The limit reached, e.g. `1000 requests/day`, as sent in `X-Quota-Limit`.
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit: &str = if self.bytes { "bytes" } else { "requests" };
+        write!(f, "{} {}/{}", self.limit, unit, self.period)
+    }
+}
+
+/// Request and byte quotas per client, refused with 429 once used up.
+pub struct Quotas {
+    identity: ClientIdentity,
+    limits: Vec<QuotaLimit>,
+    store: Arc<dyn QuotaStore>,
+}
+
+impl Quotas {
+    pub fn new(identity: ClientIdentity) -> Quotas {
+        Quotas {
+            identity,
+            limits: vec![],
+            store: Arc::new(MemoryQuotaStore::default()),
+        }
+    }
+
+    pub fn limit(&mut self, limit: QuotaLimit) -> &mut Self {
+        self.limits.push(limit);
+        self
+    }
+
+    /// Keeps usage in `store` instead of in memory.
+    pub fn store(&mut self, store: impl QuotaStore + 'static) -> &mut Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    pub(crate) fn store_handle(&self) -> Arc<dyn QuotaStore> {
+        Arc::clone(&self.store)
+    }
+
+    pub(crate) fn client_of(&self, req: &Request) -> Option<String> {
+        self.identity.of(req)
+    }
+
+    /// The first quota `client` has used up at `now`. If there is none,
+    /// the request is counted against every quota straight away, so
+    /// requests checked at the same time can't all slip under a limit.
+    pub(crate) fn check(&self, client: &str, now: SystemTime) -> Option<QuotaExceeded> {
+        let now_secs: u64 = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let limits: Vec<(QuotaLimit, u64)> = self
+            .limits
+            .iter()
+            .map(|limit| (*limit, limit.period.bounds(now).0))
+            .collect();
+
+        let (i, used) = self.store.try_consume(client, &limits).err()?;
+        let limit: &QuotaLimit = &self.limits[i];
+        let (limit_reached, bytes) = limit.reached(&used)?;
+        let (_, end) = limit.period.bounds(now);
+
+        Some(QuotaExceeded {
+            period: limit.period,
+            limit: limit_reached,
+            bytes,
+            reset_after: Duration::from_secs(end.saturating_sub(now_secs)),
+        })
+    }
+
+    /// Adds the bytes of a request [`Quotas::check`] let through and
+    /// already counted.
+    pub(crate) fn record(&self, client: &str, now: SystemTime, received: usize, sent: usize) {
+        let mut periods: Vec<QuotaPeriod> = vec![];
+        for limit in &self.limits {
+            if !periods.contains(&limit.period) {
+                periods.push(limit.period);
+            }
+        }
+
+        let usage: Transfer = Transfer {
+            requests: 0,
+            ..Transfer::request(received, sent)
+        };
+
+        for period in periods {
+            let (start, _) = period.bounds(now);
+            self.store.record(client, period, start, usage);
+        }
+    }
+}
+
+impl fmt::Debug for Quotas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Quotas")
+            .field("identity", &self.identity)
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The year, month (1 to 12) and day of the month `days` after 1970-01-01.
+/// Howard Hinnant's `civil_from_days`, for dates after the epoch.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z: u64 = days + 719_468;
+    let era: u64 = z / 146_097;
+    let doe: u64 = z - era * 146_097;
+    let yoe: u64 = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy: u64 = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp: u64 = (5 * doy + 2) / 153;
+    let day: u64 = doy - (153 * mp + 2) / 5 + 1;
+    let month: u64 = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year: u64 = yoe + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Days from 1970-01-01 to the first of `month` in `year`.
+fn days_from_civil(year: u64, month: u64) -> u64 {
+    let year: u64 = if month <= 2 { year - 1 } else { year };
+    let era: u64 = year / 400;
+    let yoe: u64 = year - era * 400;
+    let mp: u64 = if month > 2 { month - 3 } else { month + 9 };
+    let doy: u64 = (153 * mp + 2) / 5;
+    let doe: u64 = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const DAILY: QuotaLimit = QuotaLimit {
+        period: QuotaPeriod::Daily,
+        max_requests: Some(10),
+        max_bytes: None,
+    };
+
+    #[test]
+    fn concurrent_requests_stay_within_the_limit() {
+        let store: MemoryQuotaStore = MemoryQuotaStore::default();
+        let limits: [(QuotaLimit, u64); 1] = [(DAILY, 0)];
+
+        let served: usize = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..10)
+                            .filter(|_| store.try_consume("a", &limits).is_ok())
+                            .count()
+                    })
+                })
+                .collect();
+
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        assert_eq!(served, 10);
+        assert_eq!(store.usage("a", QuotaPeriod::Daily, 0).requests, 10);
+    }
+
+    #[test]
+    fn refused_request_is_not_counted() {
+        let store: MemoryQuotaStore = MemoryQuotaStore::default();
+        let bytes: QuotaLimit = QuotaLimit {
+            max_requests: None,
+            max_bytes: Some(100),
+            ..DAILY
+        };
+        let limits: [(QuotaLimit, u64); 2] = [(DAILY, 0), (bytes, 0)];
+
+        assert!(store.try_consume("a", &limits).is_ok());
+        store.record("a", QuotaPeriod::Daily, 0, Transfer::request(50, 50));
+
+        let (i, used) = store.try_consume("a", &limits).unwrap_err();
+        assert_eq!(i, 1);
+        assert_eq!(used.requests, 2);
+        assert_eq!(store.usage("a", QuotaPeriod::Daily, 0).requests, 2);
+    }
+
+    #[test]
+    fn memory_store_drops_past_periods() {
+        let store: MemoryQuotaStore = MemoryQuotaStore::default();
+        let monthly: QuotaLimit = QuotaLimit {
+            period: QuotaPeriod::Monthly,
+            ..DAILY
+        };
+
+        store.try_consume("a", &[(DAILY, 0), (monthly, 0)]).unwrap();
+        store.try_consume("b", &[(DAILY, 0), (monthly, 0)]).unwrap();
+        store
+            .try_consume("c", &[(DAILY, SECS_PER_DAY), (monthly, 0)])
+            .unwrap();
+
+        let usage = store.usage.lock().unwrap();
+        assert_eq!(usage.clients.len(), 4);
+        assert!(
+            usage
+                .clients
+                .contains_key(&(String::from("c"), QuotaPeriod::Daily))
+        );
+        drop(usage);
+
+        assert_eq!(store.usage("a", QuotaPeriod::Daily, 0), Transfer::default());
+        assert_eq!(store.usage("a", QuotaPeriod::Monthly, 0).requests, 1);
+    }
+}
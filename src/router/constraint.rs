@@ -0,0 +1,444 @@
+use core::fmt;
+use std::{error::Error, str::FromStr};
+
+/// A restriction on the values a dynamic path part accepts, written as
+/// `:name<constraint>` in a route path.
+///
+/// The constraint is either a primitive type name (`u32`, `i64`, `bool`, ...)
+/// or a pattern made of literal characters and character classes with
+/// quantifiers, e.g. `[a-z0-9-]+` or `v[0-9]{1,3}`. `\d`, `\w` and `\s`
+/// and their negations `\D`, `\W` and `\S` are shorthand classes; any
+/// other escaped letter or digit is an error. Patterns must match the
+/// whole part.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamConstraint {
+    Type(ParamType),
+    Pattern(Pattern),
+}
+
+impl ParamConstraint {
+    pub fn is_satisfied_by(&self, val: &str) -> bool {
+        match self {
+            ParamConstraint::Type(param_type) => param_type.accepts(val),
+            ParamConstraint::Pattern(pattern) => pattern.is_match(val),
+        }
+    }
+}
+
+impl FromStr for ParamConstraint {
+    type Err = ConstraintParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match ParamType::from_str(s) {
+            Ok(param_type) => Ok(ParamConstraint::Type(param_type)),
+            Err(_) => Ok(ParamConstraint::Pattern(Pattern::from_str(s)?)),
+        }
+    }
+}
+
+impl fmt::Display for ParamConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamConstraint::Type(param_type) => write!(f, "{}", param_type),
+            ParamConstraint::Pattern(pattern) => write!(f, "{}", pattern.source),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Isize,
+    F32,
+    F64,
+    Bool,
+}
+
+impl ParamType {
+    fn accepts(&self, val: &str) -> bool {
+        match self {
+            ParamType::U8 => val.parse::<u8>().is_ok(),
+            ParamType::U16 => val.parse::<u16>().is_ok(),
+            ParamType::U32 => val.parse::<u32>().is_ok(),
+            ParamType::U64 => val.parse::<u64>().is_ok(),
+            ParamType::U128 => val.parse::<u128>().is_ok(),
+            ParamType::Usize => val.parse::<usize>().is_ok(),
+            ParamType::I8 => val.parse::<i8>().is_ok(),
+            ParamType::I16 => val.parse::<i16>().is_ok(),
+            ParamType::I32 => val.parse::<i32>().is_ok(),
+            ParamType::I64 => val.parse::<i64>().is_ok(),
+            ParamType::I128 => val.parse::<i128>().is_ok(),
+            ParamType::Isize => val.parse::<isize>().is_ok(),
+            ParamType::F32 => val.parse::<f32>().is_ok(),
+            ParamType::F64 => val.parse::<f64>().is_ok(),
+            ParamType::Bool => val.parse::<bool>().is_ok(),
+        }
+    }
+}
+
+impl FromStr for ParamType {
+    type Err = ConstraintParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u8" => Ok(ParamType::U8),
+            "u16" => Ok(ParamType::U16),
+            "u32" => Ok(ParamType::U32),
+            "u64" => Ok(ParamType::U64),
+            "u128" => Ok(ParamType::U128),
+            "usize" => Ok(ParamType::Usize),
+            "i8" => Ok(ParamType::I8),
+            "i16" => Ok(ParamType::I16),
+            "i32" => Ok(ParamType::I32),
+            "i64" => Ok(ParamType::I64),
+            "i128" => Ok(ParamType::I128),
+            "isize" => Ok(ParamType::Isize),
+            "f32" => Ok(ParamType::F32),
+            "f64" => Ok(ParamType::F64),
+            "bool" => Ok(ParamType::Bool),
+            _ => Err(ConstraintParseError),
+        }
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            ParamType::U8 => "u8",
+            ParamType::U16 => "u16",
+            ParamType::U32 => "u32",
+            ParamType::U64 => "u64",
+            ParamType::U128 => "u128",
+            ParamType::Usize => "usize",
+            ParamType::I8 => "i8",
+            ParamType::I16 => "i16",
+            ParamType::I32 => "i32",
+            ParamType::I64 => "i64",
+            ParamType::I128 => "i128",
+            ParamType::Isize => "isize",
+            ParamType::F32 => "f32",
+            ParamType::F64 => "f64",
+            ParamType::Bool => "bool",
+        };
+
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    source: String,
+    atoms: Vec<Atom>,
+}
+
+impl Pattern {
+    /// Runs the pattern over `val` as a set of `(atom, repetitions)`
+    /// states, so matching takes time linear in the length of `val`
+    /// however the atoms are quantified.
+    pub fn is_match(&self, val: &str) -> bool {
+        let mut states: Vec<(usize, usize)> = self.closure(vec![(0, 0)]);
+
+        for c in val.chars() {
+            let next: Vec<(usize, usize)> = states
+                .iter()
+                .filter_map(|&(i, count)| {
+                    let atom: &Atom = self.atoms.get(i)?;
+                    let more: bool = atom.max.is_none_or(|max| count < max);
+
+                    (more && atom.matcher.matches(c)).then(|| match atom.max {
+                        // Past the minimum, unbounded repetitions are all
+                        // the same state.
+                        None => (i, (count + 1).min(atom.min)),
+                        Some(_) => (i, count + 1),
+                    })
+                })
+                .collect();
+
+            states = self.closure(next);
+            if states.is_empty() {
+                return false;
+            }
+        }
+
+        states.contains(&(self.atoms.len(), 0))
+    }
+
+    /// Adds the states reachable by moving past atoms that have been
+    /// repeated often enough.
+    fn closure(&self, mut states: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        let mut i: usize = 0;
+
+        while i < states.len() {
+            let (atom, count) = states[i];
+            let done: bool = self.atoms.get(atom).is_some_and(|a| count >= a.min);
+
+            if done && !states.contains(&(atom + 1, 0)) {
+                states.push((atom + 1, 0));
+            }
+            i += 1;
+        }
+
+        states.sort_unstable();
+        states.dedup();
+
+        states
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = ConstraintParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ConstraintParseError);
+        }
+
+        let mut chars = s.chars().peekable();
+        let mut atoms: Vec<Atom> = vec![];
+
+        while let Some(c) = chars.next() {
+            let matcher: CharMatcher = match c {
+                '[' => parse_class(&mut chars)?,
+                '.' => CharMatcher::Any,
+                '\\' => parse_escape(chars.next().ok_or(ConstraintParseError)?)?,
+                '*' | '+' | '?' | '{' | ']' | '}' | '(' | ')' | '|' => {
+                    return Err(ConstraintParseError);
+                }
+                c => CharMatcher::Literal(c),
+            };
+
+            let (min, max) = if chars.next_if_eq(&'*').is_some() {
+                (0, None)
+            } else if chars.next_if_eq(&'+').is_some() {
+                (1, None)
+            } else if chars.next_if_eq(&'?').is_some() {
+                (0, Some(1))
+            } else if chars.next_if_eq(&'{').is_some() {
+                parse_repetition(&mut chars)?
+            } else {
+                (1, Some(1))
+            };
+
+            atoms.push(Atom { matcher, min, max });
+        }
+
+        Ok(Pattern {
+            source: String::from(s),
+            atoms,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Atom {
+    matcher: CharMatcher,
+    min: usize,
+    max: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CharMatcher {
+    Any,
+    Literal(char),
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl CharMatcher {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            CharMatcher::Any => true,
+            CharMatcher::Literal(l) => *l == c,
+            CharMatcher::Class { negated, ranges } => {
+                ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != *negated
+            }
+        }
+    }
+}
+
+/// The matcher for `\c`: a shorthand class, or `c` itself when it is
+/// punctuation.
+fn parse_escape(c: char) -> Result<CharMatcher, ConstraintParseError> {
+    let class = |negated: bool, ranges: &[(char, char)]| CharMatcher::Class {
+        negated,
+        ranges: ranges.to_vec(),
+    };
+
+    match c {
+        'd' | 'D' => Ok(class(c == 'D', &DIGIT)),
+        'w' | 'W' => Ok(class(c == 'W', &WORD)),
+        's' | 'S' => Ok(class(c == 'S', &SPACE)),
+        c if c.is_alphanumeric() => Err(ConstraintParseError),
+        c => Ok(CharMatcher::Literal(c)),
+    }
+}
+
+const DIGIT: [(char, char); 1] = [('0', '9')];
+const WORD: [(char, char); 4] = [('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: [(char, char); 3] = [('\t', '\r'), (' ', ' '), ('\u{85}', '\u{85}')];
+
+fn parse_class<I: Iterator<Item = char>>(
+    chars: &mut std::iter::Peekable<I>,
+) -> Result<CharMatcher, ConstraintParseError> {
+    let negated: bool = chars.next_if_eq(&'^').is_some();
+    let mut ranges: Vec<(char, char)> = vec![];
+
+    loop {
+        let lo: char = match chars.next() {
+            Some(']') if !ranges.is_empty() => break,
+            Some('\\') => match parse_escape(chars.next().ok_or(ConstraintParseError)?)? {
+                CharMatcher::Literal(c) => c,
+                CharMatcher::Class {
+                    negated: false,
+                    ranges: shorthand,
+                } => {
+                    ranges.extend(shorthand);
+                    continue;
+                }
+                _ => return Err(ConstraintParseError),
+            },
+            Some(c) => c,
+            None => return Err(ConstraintParseError),
+        };
+
+        if chars.next_if_eq(&'-').is_some() {
+            match chars.peek() {
+                Some(']') => {
+                    ranges.push((lo, lo));
+                    ranges.push(('-', '-'));
+                }
+                Some(_) => {
+                    let hi: char = match chars.next() {
+                        Some('\\') => {
+                            match parse_escape(chars.next().ok_or(ConstraintParseError)?)? {
+                                CharMatcher::Literal(c) => c,
+                                _ => return Err(ConstraintParseError),
+                            }
+                        }
+                        Some(c) => c,
+                        None => return Err(ConstraintParseError),
+                    };
+
+                    if hi < lo {
+                        return Err(ConstraintParseError);
+                    }
+
+                    ranges.push((lo, hi));
+                }
+                None => return Err(ConstraintParseError),
+            }
+        } else {
+            ranges.push((lo, lo));
+        }
+    }
+
+    Ok(CharMatcher::Class { negated, ranges })
+}
+
+/// The largest `{n,m}` bound, which caps how many states an atom can
+/// add to a match.
+const MAX_REPETITION: usize = 1024;
+
+fn parse_repetition<I: Iterator<Item = char>>(
+    chars: &mut std::iter::Peekable<I>,
+) -> Result<(usize, Option<usize>), ConstraintParseError> {
+    let mut body: String = String::new();
+
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) => body.push(c),
+            None => return Err(ConstraintParseError),
+        }
+    }
+
+    let parse_bound = |s: &str| s.trim().parse::<usize>().map_err(|_| ConstraintParseError);
+
+    let (min, max) = match body.split_once(',') {
+        Some((min, "")) => (parse_bound(min)?, None),
+        Some((min, max)) => (parse_bound(min)?, Some(parse_bound(max)?)),
+        None => {
+            let n: usize = parse_bound(&body)?;
+            (n, Some(n))
+        }
+    };
+
+    if max.is_some_and(|max| max < min) || max.unwrap_or(min) > MAX_REPETITION {
+        return Err(ConstraintParseError);
+    }
+
+    Ok((min, max))
+}
+
+#[derive(Debug)]
+pub struct ConstraintParseError;
+
+impl fmt::Display for ConstraintParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to Parse path parameter constraint")
+    }
+}
+
+impl Error for ConstraintParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Pattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn shorthand_classes_match_their_characters() {
+        assert!(pattern(r"\d+").is_match("2024"));
+        assert!(!pattern(r"\d+").is_match("d"));
+        assert!(pattern(r"\w{3}").is_match("a_9"));
+        assert!(pattern(r"\D\W").is_match("a-"));
+        assert!(pattern(r"[\d.]+").is_match("1.2.3"));
+    }
+
+    #[test]
+    fn escaped_punctuation_is_literal() {
+        assert!(pattern(r"v\.\d").is_match("v.1"));
+        assert!(!pattern(r"v\.\d").is_match("vx1"));
+    }
+
+    #[test]
+    fn unknown_escapes_and_huge_repetitions_are_rejected() {
+        for s in [r"\q", r"\1", r"[\x]", r"[a-\d]", r"[\D]", "a{2000}"] {
+            assert!(s.parse::<Pattern>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn quantifiers_match_whole_value() {
+        assert!(pattern("a*b?c{2,3}").is_match("aaacc"));
+        assert!(pattern("a*b?c{2,3}").is_match("bccc"));
+        assert!(!pattern("a*b?c{2,3}").is_match("cccc"));
+        assert!(!pattern("a+").is_match(""));
+        assert!(pattern("a{2,}").is_match("aaaaa"));
+        assert!(!pattern("a{2,}").is_match("a"));
+    }
+
+    #[test]
+    fn stacked_stars_match_without_backtracking() {
+        let pattern: Pattern = pattern(".*.*.*.*.*.*.*.*.*.*.*.*b");
+
+        // Backtracking would try every split of the run of `a`s.
+        assert!(!pattern.is_match(&"a".repeat(10_000)));
+        assert!(pattern.is_match(&format!("{}b", "a".repeat(10_000))));
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::http::etag::ETag;
+use crate::http::media_type::MediaType;
+use crate::http::{ContentType, Request, Response, StatusCode};
+
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(86_400);
+
+/// A fixed document served from memory, such as `robots.txt` or a file
+/// under `/.well-known/`. Requests whose `If-None-Match` holds the
+/// document's tag are answered with 304.
+#[derive(Debug, Clone)]
+pub struct Document {
+    body: Vec<u8>,
+    media_type: MediaType,
+    etag: ETag,
+    max_age: Duration,
+}
+
+impl Document {
+    /// A document caches may keep for a day.
+    pub fn new(body: impl Into<Vec<u8>>, media_type: MediaType) -> Document {
+        let body: Vec<u8> = body.into();
+
+        Document {
+            etag: ETag::of(&body),
+            body,
+            media_type,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// A `text/plain; charset=utf-8` document.
+    pub fn text(body: &str) -> Document {
+        let mut media_type: MediaType = MediaType::new("text", "plain");
+        media_type
+            .params
+            .push((String::from("charset"), String::from("utf-8")));
+
+        Document::new(body, media_type)
+    }
+
+    /// Reads the document from `path` once, now. Later changes to the file
+    /// are not picked up.
+    pub fn read(path: &Path, media_type: MediaType) -> io::Result<Document> {
+        Ok(Document::new(fs::read(path)?, media_type))
+    }
+
+    /// How long caches may keep the document, sent as `Cache-Control`.
+    pub fn max_age(mut self, max_age: Duration) -> Document {
+        self.max_age = max_age;
+        self
+    }
+
+    pub(super) fn respond(&self, req: &Request) -> Response {
+        let mut res: Response = Response::from_bytes(
+            req,
+            StatusCode::OK,
+            Some(self.body.clone()),
+            ContentType::Media(Box::new(self.media_type.clone())),
+            req.protocol,
+        );
+
+        match res.precondition(req, &self.etag) {
+            Some(failed) if failed.code() == StatusCode::PRECONDITION_FAILED => return failed,
+            // A 304 refreshes how long caches may keep their copy.
+            Some(not_modified) => res = not_modified,
+            None => {}
+        }
+        res.add_header(
+            "Cache-Control",
+            format!("public, max-age={}", self.max_age.as_secs()),
+        );
+
+        res
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use tracing::info;
+
+use crate::de;
+use crate::http::{Request, Response};
+
+use super::HandlerFn;
+use super::params;
+
+/// A value that can be pulled out of an incoming request before a typed
+/// handler runs. Returning `Err` short-circuits the handler with that
+/// response.
+pub trait FromRequest<Ctx>: Sized {
+    fn from_request(
+        req: &Request,
+        params: &params::PathParams,
+        ctx: &Ctx,
+    ) -> Result<Self, Response>;
+}
+
+/// Dynamic path parts deserialized into `T`, e.g. a struct with an `id: u32`
+/// field for `/users/:id`.
+#[derive(Debug)]
+pub struct PathParams<T>(pub T);
+
+impl<Ctx, T: DeserializeOwned> FromRequest<Ctx> for PathParams<T> {
+    fn from_request(
+        _req: &Request,
+        params: &params::PathParams,
+        _ctx: &Ctx,
+    ) -> Result<Self, Response> {
+        de::from_pairs(params.iter()).map(PathParams).map_err(|e| {
+            info!(error = e.to_string(), "Failed to extract path params");
+            Response::bad_request()
+        })
+    }
+}
+
+/// The request body deserialized from JSON into `T`.
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<Ctx, T: DeserializeOwned> FromRequest<Ctx> for Json<T> {
+    fn from_request(
+        req: &Request,
+        _params: &params::PathParams,
+        _ctx: &Ctx,
+    ) -> Result<Self, Response> {
+        req.json().map(Json).map_err(|e| {
+            info!(error = e.to_string(), "Failed to extract JSON body");
+            Response::bad_request()
+        })
+    }
+}
+
+/// The application context, for servers whose context is an `Arc<T>`.
+/// Only the `Arc` is cloned, never the context behind it.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T> FromRequest<Arc<T>> for State<T> {
+    fn from_request(
+        _req: &Request,
+        _params: &params::PathParams,
+        ctx: &Arc<T>,
+    ) -> Result<Self, Response> {
+        Ok(State(Arc::clone(ctx)))
+    }
+}
+
+/// The tenant the request was resolved to. Requests for no tenant are
+/// answered with 404.
+#[derive(Debug)]
+pub struct Tenant(pub String);
+
+impl<Ctx> FromRequest<Ctx> for Tenant {
+    fn from_request(
+        req: &Request,
+        _params: &params::PathParams,
+        _ctx: &Ctx,
+    ) -> Result<Self, Response> {
+        req.tenant()
+            .map(|id| Tenant(String::from(id)))
+            .ok_or_else(Response::not_found)
+    }
+}
+
+/// The raw request body.
+#[derive(Debug)]
+pub struct Body(pub Vec<u8>);
+
+impl<Ctx> FromRequest<Ctx> for Body {
+    fn from_request(
+        req: &Request,
+        _params: &params::PathParams,
+        _ctx: &Ctx,
+    ) -> Result<Self, Response> {
+        Ok(Body(req.body.clone()))
+    }
+}
+
+/// A function whose arguments are all [`FromRequest`] extractors.
+pub trait TypedHandler<Ctx, Args>: Send + Sync + 'static {
+    fn call(&self, req: &Request, params: params::PathParams, ctx: &Ctx) -> Response;
+}
+
+macro_rules! impl_typed_handler {
+    ($($arg:ident),*) => {
+        impl<Ctx, F, $($arg,)*> TypedHandler<Ctx, ($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Response + Send + Sync + 'static,
+            $($arg: FromRequest<Ctx>,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(
+                &self,
+                req: &Request,
+                params: params::PathParams,
+                ctx: &Ctx,
+            ) -> Response {
+                $(
+                    let $arg = match $arg::from_request(req, &params, ctx) {
+                        Ok(val) => val,
+                        Err(res) => return res,
+                    };
+                )*
+
+                self($($arg),*)
+            }
+        }
+    };
+}
+
+impl_typed_handler!();
+impl_typed_handler!(A1);
+impl_typed_handler!(A1, A2);
+impl_typed_handler!(A1, A2, A3);
+impl_typed_handler!(A1, A2, A3, A4);
+impl_typed_handler!(A1, A2, A3, A4, A5);
+impl_typed_handler!(A1, A2, A3, A4, A5, A6);
+
+/// Adapts a typed handler so it can be registered like any other handler:
+/// `router.get("/users/:id", typed(show_user))`.
+pub fn typed<Ctx, Args, H>(handler: H) -> impl HandlerFn<Ctx>
+where
+    Ctx: 'static,
+    Args: 'static,
+    H: TypedHandler<Ctx, Args>,
+{
+    move |req: &Request, params: params::PathParams, ctx: &Ctx| handler.call(req, params, ctx)
+}
@@ -0,0 +1,83 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::http::{QueryError, Request, Response, StatusCode};
+
+use super::HandlerFn;
+use super::params::{ParamError, PathParams};
+
+/// A failure returned by a [`fallible`] handler. The router turns it into a
+/// response, through [`Router::on_error`](super::Router::on_error) when set.
+#[derive(Error, Debug)]
+pub enum HandlerError {
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("Unprocessable content: {0}")]
+    Unprocessable(String),
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl HandlerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HandlerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            HandlerError::NotFound(_) => StatusCode::NOT_FOUND,
+            HandlerError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            HandlerError::Unprocessable(_) => StatusCode::UNPROCESSABLE_CONTENT,
+            HandlerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<io::Error> for HandlerError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => HandlerError::NotFound(e.to_string()),
+            io::ErrorKind::InvalidInput => HandlerError::BadRequest(e.to_string()),
+            _ => HandlerError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<QueryError> for HandlerError {
+    fn from(e: QueryError) -> Self {
+        HandlerError::BadRequest(e.to_string())
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<crate::http::JsonError> for HandlerError {
+    fn from(e: crate::http::JsonError) -> Self {
+        HandlerError::BadRequest(e.to_string())
+    }
+}
+
+impl From<ParamError> for HandlerError {
+    fn from(e: ParamError) -> Self {
+        HandlerError::BadRequest(e.to_string())
+    }
+}
+
+pub type ErrorHandler = Box<dyn Fn(&Request, &HandlerError) -> Response + Send + Sync + 'static>;
+
+/// Adapts a handler returning `Result<Response, HandlerError>` so it can be
+/// registered like any other handler and use `?` on its failure paths.
+pub fn fallible<Ctx, F>(handler: F) -> impl HandlerFn<Ctx>
+where
+    Ctx: 'static,
+    F: Fn(&Request, PathParams, &Ctx) -> Result<Response, HandlerError> + Send + Sync + 'static,
+{
+    move |req: &Request, params: PathParams, ctx: &Ctx| {
+        handler(req, params, ctx).unwrap_or_else(Response::from_handler_error)
+    }
+}
@@ -0,0 +1,96 @@
+use crate::http::ReqType;
+
+use super::{
+    Handler, HandlerFn, RouteError, Router, parse_method,
+    path::Path,
+    route::{MethodFilter, Route},
+};
+
+/// Routes registered through [`Router::scope`], sharing a common path prefix.
+#[derive(Debug)]
+pub struct RouteGroup<'a, Ctx: Send + Sync> {
+    router: &'a mut Router<Ctx>,
+    prefix: Path,
+}
+
+impl<'a, Ctx: Send + Sync> RouteGroup<'a, Ctx> {
+    pub(super) fn new(router: &'a mut Router<Ctx>, prefix: Path) -> RouteGroup<'a, Ctx> {
+        RouteGroup { router, prefix }
+    }
+
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    pub fn get(
+        &mut self,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(ReqType::Get.into(), path, Box::new(handler))
+    }
+
+    pub fn post(
+        &mut self,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(ReqType::Post.into(), path, Box::new(handler))
+    }
+
+    pub fn any(
+        &mut self,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(MethodFilter::Any, path, Box::new(handler))
+    }
+
+    pub fn route(
+        &mut self,
+        methods: &[ReqType],
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(
+            MethodFilter::Only(methods.to_vec()),
+            path,
+            Box::new(handler),
+        )
+    }
+
+    pub fn method(
+        &mut self,
+        method: &str,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(parse_method(method)?.into(), path, Box::new(handler))
+    }
+
+    pub fn scope<F>(&mut self, prefix: &str, f: F) -> Result<(), RouteError>
+    where
+        F: FnOnce(&mut RouteGroup<Ctx>) -> Result<(), RouteError>,
+    {
+        let prefix: Path = self.prefix.join(&Path::parse(prefix)?);
+
+        f(&mut RouteGroup::new(self.router, prefix))
+    }
+
+    pub fn mount(&mut self, prefix: &str, other: Router<Ctx>) -> Result<(), RouteError> {
+        let prefix: Path = self.prefix.join(&Path::parse(prefix)?);
+
+        self.router.merge(&prefix, other)
+    }
+
+    fn add_route(
+        &mut self,
+        methods: MethodFilter,
+        path: &str,
+        handler: Handler<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        let path: Path = self.prefix.join(&Path::parse(path)?);
+
+        self.router.insert_route(Route::new(methods, path, handler))
+    }
+}
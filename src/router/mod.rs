@@ -1,55 +1,632 @@
-use std::collections::HashMap;
+use std::{fmt, time::Duration};
 
+use thiserror::Error;
+use tracing::info;
+
+use document::Document;
+use fallible::{ErrorHandler, HandlerError};
+use group::RouteGroup;
+use params::PathParams;
 use path::{Path, PathParseError};
-use route::Route;
+use route::{MethodFilter, Route};
+use sitemap::Sitemap;
+use tenant::{TenantSource, Tenants};
 
 use crate::http::{ReqType, Request, Response};
 
+mod constraint;
+pub mod document;
+pub mod extract;
+pub mod fallible;
+pub mod group;
+pub mod params;
 pub mod path;
 pub mod route;
+pub mod sitemap;
+pub mod tenant;
 
-#[derive(Debug)]
 pub struct Router<Ctx: Send + Sync> {
     routes: Vec<Route<Ctx>>,
+    fallback: Option<Handler<Ctx>>,
+    error_handler: Option<ErrorHandler>,
+    trailing_slash: TrailingSlash,
+    hosts: Vec<VirtualHost<Ctx>>,
+    tenant_source: Option<TenantSource>,
+    tenants: Vec<VirtualHost<Ctx>>,
+    sitemap: Option<Sitemap>,
+}
+
+struct VirtualHost<Ctx: Send + Sync> {
+    name: String,
+    router: Router<Ctx>,
+    ctx: Option<Ctx>,
+}
+
+/// How a request path that differs from a route only by a trailing slash is
+/// treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrailingSlash {
+    /// `/echo/hi/` does not match a route registered as `/echo/hi`.
+    Strict,
+    /// The request is answered with a 301 to the route's form of the path.
+    Redirect,
+    /// The trailing slash is not considered when matching.
+    Ignore,
 }
 
 impl<Ctx: Send + Sync> Router<Ctx> {
     pub fn new() -> Router<Ctx> {
-        Router { routes: vec![] }
+        Router {
+            routes: vec![],
+            fallback: None,
+            error_handler: None,
+            trailing_slash: TrailingSlash::Ignore,
+            hosts: vec![],
+            tenant_source: None,
+            tenants: vec![],
+            sitemap: None,
+        }
     }
 
-    pub fn get(&mut self, path: &str, handler: Handler<Ctx>) -> Result<(), PathParseError> {
-        self.add_route(ReqType::Get, path, handler)
+    pub fn get(
+        &mut self,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(ReqType::Get.into(), path, Box::new(handler))
     }
 
-    pub fn post(&mut self, path: &str, handler: Handler<Ctx>) -> Result<(), PathParseError> {
-        self.add_route(ReqType::Post, path, handler)
+    pub fn post(
+        &mut self,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(ReqType::Post.into(), path, Box::new(handler))
     }
 
-    pub fn handle_request(&self, req: Request, ctx: &Ctx) -> Response {
-        for route in &self.routes {
-            if route.req_type == req.req_type && route.path == req.path {
-                return (route.handler)(&req, route.path.get_req_param(&req.path), ctx);
+    /// Registers a route answering to every request method.
+    pub fn any(
+        &mut self,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(MethodFilter::Any, path, Box::new(handler))
+    }
+
+    /// Registers a single route answering to each of `methods`.
+    pub fn route(
+        &mut self,
+        methods: &[ReqType],
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(
+            MethodFilter::Only(methods.to_vec()),
+            path,
+            Box::new(handler),
+        )
+    }
+
+    /// Registers a route for a single method given by its token, which may
+    /// be an extension method such as `PURGE` or `PROPFIND`.
+    pub fn method(
+        &mut self,
+        method: &str,
+        path: &str,
+        handler: impl HandlerFn<Ctx>,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.add_route(parse_method(method)?.into(), path, Box::new(handler))
+    }
+
+    /// Serves `robots.txt`, e.g. `Document::text("User-agent: *\nDisallow:\n")`.
+    pub fn robots_txt(&mut self, document: Document) -> Result<&mut Route<Ctx>, RouteError> {
+        self.document("/robots.txt", document)
+    }
+
+    /// Serves `/favicon.ico`, as `image/x-icon` or any other image type.
+    pub fn favicon(&mut self, document: Document) -> Result<&mut Route<Ctx>, RouteError> {
+        self.document("/favicon.ico", document)
+    }
+
+    /// Serves `document` as `/.well-known/<name>`, e.g. `security.txt`.
+    pub fn well_known(
+        &mut self,
+        name: &str,
+        document: Document,
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.document(
+            &format!("/.well-known/{}", name.trim_start_matches('/')),
+            document,
+        )
+    }
+
+    /// Serves `sitemap` as `/sitemap.xml`, unless a route is registered for
+    /// that path.
+    pub fn sitemap(&mut self, sitemap: Sitemap) {
+        self.sitemap = Some(sitemap);
+    }
+
+    /// Registers every route added inside `f` under the shared `prefix`.
+    pub fn scope<F>(&mut self, prefix: &str, f: F) -> Result<(), RouteError>
+    where
+        F: FnOnce(&mut RouteGroup<Ctx>) -> Result<(), RouteError>,
+    {
+        let prefix: Path = Path::parse(prefix)?;
+
+        f(&mut RouteGroup::new(self, prefix))
+    }
+
+    /// Lists the registered routes, in matching order, as (method, path
+    /// pattern, name).
+    pub fn routes(&self) -> impl Iterator<Item = (&MethodFilter, &Path, Option<&str>)> {
+        self.routes
+            .iter()
+            .map(|route| (&route.methods, &route.path, route.route_name()))
+    }
+
+    /// Set before adding routes: `/a` and `/a/` only conflict when the
+    /// policy ignores the trailing slash.
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Sets the handler used for requests whose path matches no route.
+    pub fn fallback(&mut self, handler: impl HandlerFn<Ctx>) {
+        self.fallback = Some(Box::new(handler));
+    }
+
+    /// Sets how errors returned by [`fallible`](fallible::fallible) handlers
+    /// are turned into responses. Without one, each error is answered with
+    /// the bare status from [`HandlerError::status`].
+    pub fn on_error(
+        &mut self,
+        handler: impl Fn(&Request, &HandlerError) -> Response + Send + Sync + 'static,
+    ) {
+        self.error_handler = Some(Box::new(handler));
+    }
+
+    /// Dispatches requests whose `Host` header names `host` to `router`
+    /// instead of this router's own routes.
+    pub fn host(&mut self, host: &str, router: Router<Ctx>) {
+        self.add_host(host, router, None);
+    }
+
+    /// Like [`Router::host`], with `ctx` handed to the host's handlers in
+    /// place of the server-wide context.
+    pub fn host_with_ctx(&mut self, host: &str, router: Router<Ctx>, ctx: Ctx) {
+        self.add_host(host, router, Some(ctx));
+    }
+
+    /// Starts serving tenants identified by `source`, each from its own
+    /// router and context. Requests for an unknown tenant, or none, fall
+    /// through to this router's own routes. Any tenants added for an
+    /// earlier source are dropped.
+    pub fn tenants(&mut self, source: TenantSource) -> Tenants<'_, Ctx> {
+        self.tenant_source = Some(source);
+        self.tenants.clear();
+
+        Tenants::new(self)
+    }
+
+    /// Moves every route of `other` into this router under `prefix`. Only
+    /// routes can be mounted; fails if `other` has a fallback, an error
+    /// handler, virtual hosts, tenants or a sitemap, rather than dropping
+    /// them.
+    pub fn mount(&mut self, prefix: &str, other: Router<Ctx>) -> Result<(), RouteError> {
+        let prefix: Path = Path::parse(prefix)?;
+
+        self.merge(&prefix, other)
+    }
+
+    pub fn handle_request(&self, mut req: Request, ctx: &Ctx) -> Response {
+        if let Some(vhost) = self.virtual_host_for(&req) {
+            return vhost
+                .router
+                .handle_request(req, vhost.ctx.as_ref().unwrap_or(ctx));
+        }
+
+        if let Some(tenant) = self.tenant_for(&req) {
+            req.set_tenant(&tenant.name);
+            return tenant
+                .router
+                .handle_request(req, tenant.ctx.as_ref().unwrap_or(ctx));
+        }
+
+        let mut res: Response = self.dispatch(&req, ctx);
+
+        match res.take_handler_error() {
+            Some(e) => {
+                info!(error = e.to_string(), "Handler failed");
+
+                match &self.error_handler {
+                    Some(handler) => handler(&req, &e),
+                    None => Response::default_message(e.status()),
+                }
+            }
+            None => res,
+        }
+    }
+
+    /// Whether a route, on this router or one of its hosts, is registered
+    /// explicitly for `method`.
+    pub fn has_routes_for(&self, method: &ReqType) -> bool {
+        self.routes
+            .iter()
+            .any(|route| matches!(&route.methods, MethodFilter::Only(methods) if methods.contains(method)))
+            || self
+                .hosts
+                .iter()
+                .chain(&self.tenants)
+                .any(|vhost| vhost.router.has_routes_for(method))
+    }
+
+    /// Whether a route, on this router or one of its hosts, has a timeout.
+    pub(crate) fn has_timeouts(&self) -> bool {
+        self.routes.iter().any(|route| route.timeout().is_some())
+            || self
+                .hosts
+                .iter()
+                .chain(&self.tenants)
+                .any(|vhost| vhost.router.has_timeouts())
+    }
+
+    /// The timeout of the route that would answer `req`, if it has one.
+    pub fn timeout_for(&self, req: &Request) -> Option<Duration> {
+        self.route_for(req).and_then(|route| route.timeout())
+    }
+
+    /// The path pattern of the route that would answer `req`.
+    pub fn pattern_for(&self, req: &Request) -> Option<String> {
+        self.route_for(req).map(|route| route.path.to_string())
+    }
+
+    fn route_for(&self, req: &Request) -> Option<&Route<Ctx>> {
+        if let Some(vhost) = self.virtual_host_for(req).or_else(|| self.tenant_for(req)) {
+            return vhost.router.route_for(req);
+        }
+
+        self.routes.iter().find(|route| {
+            route.path.matches(&req.path)
+                && self.slash_matches(&route.path, &req.path)
+                && route.methods.allows(&req.req_type)
+        })
+    }
+
+    /// Whether the trailing slashes of `route` and `path` let one answer
+    /// the other under the policy. Routes met only through a redirect do
+    /// not.
+    fn slash_matches(&self, route: &Path, path: &Path) -> bool {
+        self.trailing_slash == TrailingSlash::Ignore
+            || route.has_trailing_slash() == path.has_trailing_slash()
+    }
+
+    fn dispatch(&self, req: &Request, ctx: &Ctx) -> Response {
+        let mut allowed: Vec<&ReqType> = vec![];
+        let mut redirect: Option<Path> = None;
+
+        for route in self
+            .routes
+            .iter()
+            .filter(|route| route.path.matches(&req.path))
+        {
+            if !self.slash_matches(&route.path, &req.path) {
+                if self.trailing_slash == TrailingSlash::Redirect
+                    && route.methods.allows(&req.req_type)
+                    && redirect.is_none()
+                {
+                    redirect = Some(
+                        req.path
+                            .with_trailing_slash(route.path.has_trailing_slash()),
+                    );
+                }
+                continue;
+            }
+
+            if route.methods.allows(&req.req_type) {
+                return route.respond(req, ctx);
+            }
+
+            if let MethodFilter::Only(methods) = &route.methods {
+                for method in methods {
+                    if !allowed.contains(&method) {
+                        allowed.push(method);
+                    }
+                }
+            }
+        }
+
+        if let Some(path) = redirect {
+            let mut location: String = path.encoded();
+            if let Some(query) = req.query.as_deref().filter(|query| !query.is_empty()) {
+                location.push('?');
+                location.push_str(query);
+            }
+
+            // A 301 lets clients retry as GET, so other methods get a 308.
+            let is_read: bool = match &req.req_type {
+                ReqType::Get => true,
+                ReqType::Other(method) => method == "HEAD",
+                _ => false,
+            };
+
+            return match is_read {
+                true => Response::moved_permanently(&location),
+                false => Response::permanent_redirect(&location),
+            };
+        }
+
+        if !allowed.is_empty() {
+            return Response::method_not_allowed(&allowed);
+        }
+
+        if let Some(sitemap) = &self.sitemap {
+            if req.req_type == ReqType::Get && req.path.encoded() == "/sitemap.xml" {
+                return sitemap.respond(req, self.indexable_paths());
             }
         }
 
-        Response::not_found()
+        match &self.fallback {
+            Some(handler) => handler(req, PathParams::default(), ctx),
+            None => Response::not_found(),
+        }
+    }
+
+    fn indexable_paths(&self) -> Vec<String> {
+        self.routes
+            .iter()
+            .filter(|route| route.is_indexable() && route.path.is_static())
+            .map(|route| route.path.encoded())
+            .collect()
+    }
+
+    fn add_host(&mut self, host: &str, router: Router<Ctx>, ctx: Option<Ctx>) {
+        let name: String = host.to_ascii_lowercase();
+        self.hosts.retain(|vhost| vhost.name != name);
+        self.hosts.push(VirtualHost { name, router, ctx });
+    }
+
+    fn virtual_host_for(&self, req: &Request) -> Option<&VirtualHost<Ctx>> {
+        if self.hosts.is_empty() {
+            return None;
+        }
+
+        let host: &str = host_name(req)?;
+
+        self.hosts
+            .iter()
+            .find(|vhost| vhost.name.eq_ignore_ascii_case(host))
+    }
+
+    fn tenant_for(&self, req: &Request) -> Option<&VirtualHost<Ctx>> {
+        let id: &str = self.tenant_source.as_ref()?.tenant_of(req)?;
+
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.name.eq_ignore_ascii_case(id))
+    }
+
+    fn document(&mut self, path: &str, document: Document) -> Result<&mut Route<Ctx>, RouteError> {
+        self.get(path, move |req: &Request, _, _: &Ctx| document.respond(req))
     }
 
     fn add_route(
         &mut self,
-        req_type: ReqType,
+        methods: MethodFilter,
         path: &str,
         handler: Handler<Ctx>,
-    ) -> Result<(), PathParseError> {
-        self.routes.push(Route {
-            req_type,
-            path: Path::parse(path)?,
-            handler,
-        });
+    ) -> Result<&mut Route<Ctx>, RouteError> {
+        self.insert_route(Route::new(methods, Path::parse(path)?, handler))
+    }
+
+    fn merge(&mut self, prefix: &Path, other: Router<Ctx>) -> Result<(), RouteError> {
+        let unmergeable: Option<&'static str> = if other.fallback.is_some() {
+            Some("a fallback")
+        } else if other.error_handler.is_some() {
+            Some("an error handler")
+        } else if !other.hosts.is_empty() {
+            Some("virtual hosts")
+        } else if other.tenant_source.is_some() {
+            Some("tenants")
+        } else if other.sitemap.is_some() {
+            Some("a sitemap")
+        } else {
+            None
+        };
+
+        if let Some(state) = unmergeable {
+            return Err(RouteError::Unmountable(state));
+        }
+
+        for route in other.routes {
+            self.insert_route(route.prefixed(prefix))?;
+        }
 
         Ok(())
     }
+
+    fn insert_route(&mut self, route: Route<Ctx>) -> Result<&mut Route<Ctx>, RouteError> {
+        if let Some(existing) = self.routes.iter().find(|r| {
+            r.methods.intersects(&route.methods)
+                && r.path.overlaps(&route.path)
+                && self.slash_matches(&r.path, &route.path)
+        }) {
+            return Err(RouteError::Conflict(RouteConflictError {
+                methods: route.methods,
+                path: route.path.to_string(),
+                existing_methods: existing.methods.clone(),
+                existing_path: existing.path.to_string(),
+            }));
+        }
+
+        let specificity: Vec<u8> = route.path.specificity();
+        let idx: usize = self
+            .routes
+            .iter()
+            .position(|r| r.path.specificity() < specificity)
+            .unwrap_or(self.routes.len());
+        self.routes.insert(idx, route);
+
+        Ok(&mut self.routes[idx])
+    }
 }
 
-pub type Handler<Ctx> = fn(&Request, Option<HashMap<String, String>>, &Ctx) -> Response;
+impl<Ctx: Send + Sync> fmt::Debug for Router<Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("routes", &self.routes)
+            .field("has_fallback", &self.fallback.is_some())
+            .field("has_error_handler", &self.error_handler.is_some())
+            .field(
+                "hosts",
+                &self
+                    .hosts
+                    .iter()
+                    .map(|vhost| (&vhost.name, &vhost.router))
+                    .collect::<Vec<_>>(),
+            )
+            .field("sitemap", &self.sitemap)
+            .field("tenant_source", &self.tenant_source)
+            .field(
+                "tenants",
+                &self
+                    .tenants
+                    .iter()
+                    .map(|tenant| (&tenant.name, &tenant.router))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// The `Host` header without its port.
+fn host_name(req: &Request) -> Option<&str> {
+    let host: &str = req.header_val("Host")?.trim();
+
+    match host.find(']') {
+        Some(end) if host.starts_with('[') => Some(&host[..=end]),
+        _ => Some(host.split_once(':').map_or(host, |(name, _)| name)),
+    }
+}
+
+impl<Ctx: Send + Sync> Default for Router<Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RouteError {
+    #[error("{0}")]
+    Path(#[from] PathParseError),
+
+    #[error("{0}")]
+    Conflict(#[from] RouteConflictError),
+
+    #[error("`{0}` is not a valid request method")]
+    InvalidMethod(String),
+
+    #[error("a mounted router can only carry routes, but this one has {0}")]
+    Unmountable(&'static str),
+}
+
+pub(crate) fn parse_method(method: &str) -> Result<ReqType, RouteError> {
+    method
+        .parse()
+        .map_err(|_| RouteError::InvalidMethod(String::from(method)))
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "{methods} {path} conflicts with the already registered {existing_methods} {existing_path}"
+)]
+pub struct RouteConflictError {
+    pub methods: MethodFilter,
+    pub path: String,
+    pub existing_methods: MethodFilter,
+    pub existing_path: String,
+}
+
+pub type Handler<Ctx> = Box<dyn HandlerFn<Ctx>>;
+
+pub trait HandlerFn<Ctx>:
+    Fn(&Request, PathParams, &Ctx) -> Response + Send + Sync + 'static
+{
+}
+
+impl<Ctx, F> HandlerFn<Ctx> for F where
+    F: Fn(&Request, PathParams, &Ctx) -> Response + Send + Sync + 'static
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect_router() -> Router<()> {
+        let mut router: Router<()> = Router::new();
+        router.trailing_slash(TrailingSlash::Redirect);
+        router
+            .get("/echo/hi", |_: &Request, _, _: &()| Response::success())
+            .unwrap();
+        router
+            .post("/echo/hi", |_: &Request, _, _: &()| Response::success())
+            .unwrap();
+        router
+            .method("HEAD", "/echo/hi", |_: &Request, _, _: &()| {
+                Response::success()
+            })
+            .unwrap();
+
+        router
+    }
+
+    /// The status line and `Location` of the response to `head`.
+    fn redirect_of(router: &Router<()>, head: &str) -> (String, Option<String>) {
+        let req: Request = Request::new(head.as_bytes()).unwrap();
+        let res: Response = router.handle_request(req, &());
+        let bytes: Vec<u8> = res.to_bytes();
+        let raw: String = String::from_utf8_lossy(&bytes).into_owned();
+
+        let status: String = raw.lines().next().unwrap_or_default().to_string();
+        let location: Option<String> = raw
+            .lines()
+            .find_map(|line| line.strip_prefix("Location: "))
+            .map(String::from);
+
+        (status, location)
+    }
+
+    #[test]
+    fn trailing_slash_redirect_keeps_query() {
+        let (status, location) =
+            redirect_of(&redirect_router(), "GET /echo/hi/?x=1&y=2 HTTP/1.1\r\n\r\n");
+
+        assert_eq!(status, "HTTP/1.1 301 Moved Permanently");
+        assert_eq!(location.as_deref(), Some("/echo/hi?x=1&y=2"));
+    }
+
+    #[test]
+    fn trailing_slash_redirect_without_query() {
+        let (_, location) = redirect_of(&redirect_router(), "GET /echo/hi/ HTTP/1.1\r\n\r\n");
+
+        assert_eq!(location.as_deref(), Some("/echo/hi"));
+    }
+
+    #[test]
+    fn trailing_slash_redirect_of_head_is_301() {
+        let (status, _) = redirect_of(&redirect_router(), "HEAD /echo/hi/ HTTP/1.1\r\n\r\n");
+
+        assert_eq!(status, "HTTP/1.1 301 Moved Permanently");
+    }
+
+    #[test]
+    fn trailing_slash_redirect_keeps_post_a_post() {
+        let (status, location) = redirect_of(
+            &redirect_router(),
+            "POST /echo/hi/?x=1 HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+        );
+
+        assert_eq!(status, "HTTP/1.1 308 Permanent Redirect");
+        assert_eq!(location.as_deref(), Some("/echo/hi?x=1"));
+    }
+}
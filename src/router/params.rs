@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// The dynamic parts of a matched route, by name: `id` for `/users/:id`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathParams {
+    params: HashMap<String, String>,
+}
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Parses the parameter `name` into `T`.
+    pub fn get_as<T>(&self, name: &str) -> Result<T, ParamError>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let value: &str = self
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(String::from(name)))?;
+
+        value.parse().map_err(|e: T::Err| ParamError::Invalid {
+            name: String::from(name),
+            value: String::from(value),
+            reason: e.to_string(),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
+
+impl From<HashMap<String, String>> for PathParams {
+    fn from(params: HashMap<String, String>) -> Self {
+        PathParams { params }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParamError {
+    #[error("Missing path parameter `{0}`")]
+    Missing(String),
+
+    #[error("Invalid path parameter `{name}` = `{value}`: {reason}")]
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
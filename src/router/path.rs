@@ -1,34 +1,118 @@
 use core::fmt;
-use std::{collections::HashMap, error::Error, str::FromStr};
+use std::{collections::HashMap, str::FromStr};
 
+use thiserror::Error;
 use tracing::{info, instrument};
 
+use super::constraint::ParamConstraint;
+use crate::http::percent::{PercentDecodeError, percent_decode, percent_encode_segment};
+
 #[derive(Debug)]
 pub struct Path {
     parts: Vec<PathPart>,
+    trailing_slash: bool,
 }
 
 impl Path {
     #[instrument]
     pub fn parse(path: &str) -> Result<Path, PathParseError> {
-        if !path.starts_with("/") || !path.contains("/") {
-            return Err(PathParseError {});
+        if !path.starts_with('/') {
+            info!("Path Parse Error");
+            return Err(PathParseError::MissingLeadingSlash);
         }
 
-        let path_parts: Vec<&str> = path.split('/').filter(|e| !e.is_empty()).collect();
-        let path_parts: Result<Vec<PathPart>, PathPartParseError> = path_parts
+        let parts: Vec<PathPart> = path
+            .split('/')
+            .filter(|e| !e.is_empty())
+            .map(|part| {
+                PathPart::from_str(part).map_err(|source| PathParseError::InvalidPart {
+                    part: String::from(part),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<PathPart>, PathParseError>>()?;
+
+        info!(?parts, "Generated Parts");
+
+        Ok(Path {
+            trailing_slash: !parts.is_empty() && path.ends_with('/'),
+            parts,
+        })
+    }
+
+    /// Parses the path of a request target. Segments are percent-decoded
+    /// after splitting, so an encoded `/` stays inside its segment, and are
+    /// always taken literally. `.` and `..` segments are rejected.
+    pub fn parse_request(path: &str) -> Result<Path, PathParseError> {
+        if !path.starts_with('/') {
+            return Err(PathParseError::MissingLeadingSlash);
+        }
+
+        let parts: Vec<PathPart> = path
+            .split('/')
+            .filter(|e| !e.is_empty())
+            .map(|segment| {
+                PathPart::literal(segment).map_err(|source| PathParseError::InvalidPart {
+                    part: String::from(segment),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<PathPart>, PathParseError>>()?;
+
+        Ok(Path {
+            trailing_slash: !parts.is_empty() && path.ends_with('/'),
+            parts,
+        })
+    }
+
+    /// Renders the path with every part percent-encoded, for use in headers
+    /// such as `Location`.
+    pub fn encoded(&self) -> String {
+        let mut encoded: String = self
+            .parts
             .iter()
-            .map(|part| PathPart::from_str(part))
+            .map(|part| format!("/{}", percent_encode_segment(&part.part)))
             .collect();
 
-        if let Ok(parts) = path_parts {
-            info!(?parts, "Generated Parts");
-            return Ok(Path { parts });
+        if encoded.is_empty() || self.trailing_slash {
+            encoded.push('/');
         }
 
-        info!("Path Parse Error");
+        encoded
+    }
 
-        Err(PathParseError {})
+    pub fn join(&self, other: &Path) -> Path {
+        Path {
+            parts: self.parts.iter().chain(&other.parts).cloned().collect(),
+            trailing_slash: if other.parts.is_empty() {
+                self.trailing_slash
+            } else {
+                other.trailing_slash
+            },
+        }
+    }
+
+    /// The first segment, percent-decoded.
+    pub fn first_segment(&self) -> Option<&str> {
+        self.parts.first().map(|part| part.part.as_str())
+    }
+
+    /// Whether the path has no dynamic parts, so it names a single URL.
+    pub fn is_static(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|part| matches!(part.part_type, PathPartType::Static))
+    }
+
+    pub fn has_trailing_slash(&self) -> bool {
+        self.trailing_slash
+    }
+
+    pub fn with_trailing_slash(&self, trailing_slash: bool) -> Path {
+        Path {
+            parts: self.parts.clone(),
+            trailing_slash: trailing_slash && !self.parts.is_empty(),
+        }
     }
 
     pub fn get_req_param(&self, req_path: &Path) -> Option<HashMap<String, String>> {
@@ -48,11 +132,51 @@ impl Path {
         })
     }
 
+    /// Ranks each part, most significant first: static parts outrank
+    /// constrained dynamic parts, which outrank unconstrained ones.
+    pub fn specificity(&self) -> Vec<u8> {
+        self.parts
+            .iter()
+            .map(|part| match (&part.part_type, &part.constraint) {
+                (PathPartType::Static, _) => 2,
+                (PathPartType::Dynamic, Some(_)) => 1,
+                (PathPartType::Dynamic, None) => 0,
+            })
+            .collect()
+    }
+
+    /// Whether both patterns would match exactly the same request paths,
+    /// trailing slash aside.
+    pub fn overlaps(&self, other: &Path) -> bool {
+        self.parts.len() == other.parts.len()
+            && self.parts.iter().zip(&other.parts).all(|(a, b)| {
+                match (&a.part_type, &b.part_type) {
+                    (PathPartType::Static, PathPartType::Static) => a.part == b.part,
+                    (PathPartType::Dynamic, PathPartType::Dynamic) => a.constraint == b.constraint,
+                    _ => false,
+                }
+            })
+    }
+
+    pub fn matches(&self, req_path: &Path) -> bool {
+        self.get_if_matches(req_path).is_some()
+    }
+
     fn get_if_matches(&self, other: &Path) -> Option<Vec<(PathPart, PathPart)>> {
         if self != other {
             return None;
         }
 
+        let constraints_hold: bool = self.parts.iter().zip(&other.parts).all(|(a, b)| {
+            a.constraint
+                .as_ref()
+                .is_none_or(|constraint| constraint.is_satisfied_by(&b.part))
+        });
+
+        if !constraints_hold {
+            return None;
+        }
+
         Some(
             self.parts
                 .iter()
@@ -74,31 +198,93 @@ impl PartialEq for Path {
     }
 }
 
-#[derive(Debug)]
-pub struct PathParseError {}
-
-impl fmt::Display for PathParseError {
+impl fmt::Display for Path {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Failed to Parse str to a Path")
+        if self.parts.is_empty() {
+            return write!(f, "/");
+        }
+
+        for part in &self.parts {
+            write!(f, "/{}", part)?;
+        }
+
+        if self.trailing_slash {
+            write!(f, "/")?;
+        }
+
+        Ok(())
     }
 }
 
-impl Error for PathParseError {}
+#[derive(Error, Debug)]
+pub enum PathParseError {
+    #[error("Path must start with '/'")]
+    MissingLeadingSlash,
+
+    #[error("Invalid path part `{part}`: {source}")]
+    InvalidPart {
+        part: String,
+        source: PathPartParseError,
+    },
+}
 
 #[derive(Debug, Clone)]
 pub struct PathPart {
     part: String,
     part_type: PathPartType,
+    constraint: Option<ParamConstraint>,
 }
 
-#[derive(Debug)]
-pub struct PathPartParseError {}
+impl fmt::Display for PathPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.part_type, &self.constraint) {
+            (PathPartType::Static, _) => write!(f, "{}", self.part),
+            (PathPartType::Dynamic, None) => write!(f, ":{}", self.part),
+            (PathPartType::Dynamic, Some(constraint)) => {
+                write!(f, ":{}<{}>", self.part, constraint)
+            }
+        }
+    }
+}
 
-impl Error for PathPartParseError {}
+#[derive(Error, Debug)]
+pub enum PathPartParseError {
+    #[error("empty path part")]
+    Empty,
 
-impl fmt::Display for PathPartParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Failed to Parse PathPart")
+    #[error("`.` and `..` are not allowed as path parts")]
+    DotSegment,
+
+    #[error("character `{0}` is not allowed in a path part")]
+    InvalidCharacter(char),
+
+    #[error("{0}")]
+    InvalidEscape(#[from] PercentDecodeError),
+
+    #[error("parameter name `{0}` must be non-empty and made of letters, digits and `_`")]
+    InvalidParamName(String),
+
+    #[error("parameter constraint is missing its closing `>`")]
+    UnclosedConstraint,
+
+    #[error("invalid parameter constraint `{0}`")]
+    InvalidConstraint(String),
+}
+
+impl PathPart {
+    /// A static part matching exactly the percent-decoded `segment`.
+    fn literal(segment: &str) -> Result<Self, PathPartParseError> {
+        let part: String = percent_decode(segment)?;
+
+        if part == "." || part == ".." {
+            return Err(PathPartParseError::DotSegment);
+        }
+
+        Ok(Self {
+            part,
+            part_type: PathPartType::Static,
+            constraint: None,
+        })
     }
 }
 
@@ -109,29 +295,50 @@ impl FromStr for PathPart {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.is_empty() {
             info!("Empty part path");
-            return Err(PathPartParseError {});
+            return Err(PathPartParseError::Empty);
         }
 
         if let Some(stripped) = s.strip_prefix(":") {
+            let (name, constraint) = match stripped.split_once('<') {
+                Some((name, constraint)) => {
+                    let constraint: &str = constraint
+                        .strip_suffix('>')
+                        .ok_or(PathPartParseError::UnclosedConstraint)?;
+
+                    (
+                        name,
+                        Some(ParamConstraint::from_str(constraint).map_err(|_| {
+                            PathPartParseError::InvalidConstraint(String::from(constraint))
+                        })?),
+                    )
+                }
+                None => (stripped, None),
+            };
+
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(PathPartParseError::InvalidParamName(String::from(name)));
+            }
+
             Ok(Self {
-                part: stripped.to_string(),
+                part: name.to_string(),
                 part_type: PathPartType::Dynamic,
+                constraint,
             })
         } else {
-            if let Some(c) = s.chars().next() {
-                if !c.is_alphabetic() {
-                    return Err(PathPartParseError {});
-                }
+            if let Some(c) = s.chars().find(|c| !is_pchar(*c)) {
+                return Err(PathPartParseError::InvalidCharacter(c));
             }
 
-            Ok(Self {
-                part: s.to_string(),
-                part_type: PathPartType::Static,
-            })
+            Self::literal(s)
         }
     }
 }
 
+/// The characters RFC 3986 allows in a path segment, with `%` for escapes.
+fn is_pchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~!$&'()*+,;=:@%".contains(c)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 enum PathPartType {
     Static,
@@ -1,10 +1,332 @@
-use crate::http::ReqType;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use super::{Handler, path::Path};
+use tracing::error;
+
+use crate::http::etag::ETag;
+use crate::http::{AcceptedEncoding, ReqType, Request, Response};
+
+use super::{Handler, HandlerFn, params::PathParams, path::Path};
+
+const MAX_CACHED_VARIANTS: usize = 16;
 
-#[derive(Debug)]
 pub struct Route<Ctx: Send + Sync> {
-    pub req_type: ReqType,
+    pub methods: MethodFilter,
     pub path: Path,
     pub handler: Handler<Ctx>,
+    name: Option<String>,
+    response_cache: Option<ResponseCache>,
+    timeout: Option<Duration>,
+    no_transform: bool,
+    indexable: bool,
+    canary: Option<Canary<Ctx>>,
+    canary_header: Option<String>,
+}
+
+impl<Ctx: Send + Sync> Route<Ctx> {
+    pub fn new(methods: MethodFilter, path: Path, handler: Handler<Ctx>) -> Route<Ctx> {
+        Route {
+            methods,
+            path,
+            handler,
+            name: None,
+            response_cache: None,
+            timeout: None,
+            no_transform: false,
+            indexable: false,
+            canary: None,
+            canary_header: None,
+        }
+    }
+
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    pub fn route_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Marks the route's response as constant. The handler then runs once per
+    /// response variant and later requests are answered with the cached bytes.
+    pub fn immutable(&mut self) -> &mut Self {
+        self.response_cache = Some(ResponseCache::default());
+        self
+    }
+
+    pub fn is_immutable(&self) -> bool {
+        self.response_cache.is_some()
+    }
+
+    /// Bounds how long the handler may run. A request still running at the
+    /// deadline is answered with 503 and the handler's result is discarded,
+    /// but the handler is not cancelled: it runs on to completion. Handlers
+    /// with timeouts share a pool of as many threads as the server has
+    /// workers, and requests that find all of them busy, overrunning
+    /// handlers included, are answered with 503 straight away.
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sends the route's responses exactly as the handler built them: never
+    /// compressed, never cached, and marked `Cache-Control: no-transform`.
+    pub fn no_transform(&mut self) -> &mut Self {
+        self.no_transform = true;
+        self
+    }
+
+    pub fn is_no_transform(&self) -> bool {
+        self.no_transform
+    }
+
+    /// Lists the route in the router's sitemap. Only static paths are
+    /// listed; paths of dynamic routes come from a sitemap provider.
+    pub fn indexable(&mut self) -> &mut Self {
+        self.indexable = true;
+        self
+    }
+
+    pub fn is_indexable(&self) -> bool {
+        self.indexable
+    }
+
+    /// Sends `percent` of the route's requests to `handler` instead, spread
+    /// evenly over the route's traffic, so a new version of the handler can
+    /// be tried before it replaces this one. Canary responses are never
+    /// cached.
+    pub fn canary(&mut self, handler: impl HandlerFn<Ctx>, percent: u8) -> &mut Self {
+        self.canary = Some(Canary {
+            handler: Box::new(handler),
+            percent: u64::from(percent.min(100)),
+            requests: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// Lets a request pick the handler with the header `name`: `canary` for
+    /// the canary and `stable` for the route's own, whatever the split.
+    pub fn canary_header(&mut self, name: &str) -> &mut Self {
+        self.canary_header = Some(String::from(name));
+        self
+    }
+
+    pub(super) fn prefixed(self, prefix: &Path) -> Route<Ctx> {
+        Route {
+            path: prefix.join(&self.path),
+            ..self
+        }
+    }
+
+    pub fn respond(&self, req: &Request, ctx: &Ctx) -> Response {
+        let params: PathParams = self
+            .path
+            .get_req_param(&req.path)
+            .map(PathParams::from)
+            .unwrap_or_default();
+
+        if let Some(canary) = &self.canary {
+            if canary.serves(req, self.canary_header.as_deref()) {
+                let mut res: Response = (canary.handler)(req, params, ctx);
+                if self.no_transform {
+                    res.no_transform();
+                }
+
+                return res;
+            }
+        }
+
+        if self.no_transform {
+            let mut res: Response = (self.handler)(req, params, ctx);
+            res.no_transform();
+
+            return res;
+        }
+
+        match &self.response_cache {
+            Some(cache) => cache.get_or_insert(req, || (self.handler)(req, params, ctx)),
+            None => (self.handler)(req, params, ctx),
+        }
+    }
+}
+
+impl<Ctx: Send + Sync> fmt::Debug for Route<Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("methods", &self.methods)
+            .field("path", &self.path)
+            .field("name", &self.name)
+            .field("immutable", &self.is_immutable())
+            .field("timeout", &self.timeout)
+            .field("no_transform", &self.no_transform)
+            .field("indexable", &self.indexable)
+            .field("canary", &self.canary.as_ref().map(|c| c.percent))
+            .field("canary_header", &self.canary_header)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A second handler answering a share of a route's requests.
+struct Canary<Ctx> {
+    handler: Handler<Ctx>,
+    percent: u64,
+    requests: AtomicU64,
+}
+
+impl<Ctx> Canary<Ctx> {
+    fn serves(&self, req: &Request, header: Option<&str>) -> bool {
+        let choice: Option<String> = header
+            .and_then(|name| req.header_val(name))
+            .map(|val| val.trim().to_ascii_lowercase());
+
+        match choice.as_deref() {
+            Some("canary") => true,
+            Some("stable") => false,
+            _ => {
+                // Request n goes to the canary whenever percent * n / 100
+                // steps up, which is `percent` times in every 100 requests.
+                let n: u64 = self.requests.fetch_add(1, Ordering::Relaxed);
+                (n + 1) * self.percent / 100 > n * self.percent / 100
+            }
+        }
+    }
+}
+
+/// The request methods a route answers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MethodFilter {
+    Any,
+    Only(Vec<ReqType>),
+}
+
+impl MethodFilter {
+    pub fn allows(&self, method: &ReqType) -> bool {
+        match self {
+            MethodFilter::Any => true,
+            MethodFilter::Only(methods) => methods.contains(method),
+        }
+    }
+
+    pub fn intersects(&self, other: &MethodFilter) -> bool {
+        match (self, other) {
+            (MethodFilter::Only(methods), other) | (other, MethodFilter::Only(methods)) => {
+                methods.iter().any(|m| other.allows(m))
+            }
+            (MethodFilter::Any, MethodFilter::Any) => true,
+        }
+    }
+}
+
+impl From<ReqType> for MethodFilter {
+    fn from(method: ReqType) -> Self {
+        MethodFilter::Only(vec![method])
+    }
+}
+
+impl fmt::Display for MethodFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MethodFilter::Any => write!(f, "ANY"),
+            MethodFilter::Only(methods) => {
+                let methods: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+                write!(f, "{}", methods.join(", "))
+            }
+        }
+    }
+}
+
+/// Serialized responses keyed by what the request changes about how a
+/// response is written. Every part of the key is negotiated rather than
+/// copied from a header, so there are only a handful of variants. Each
+/// keeps its `ETag` so conditional requests can be answered with 304.
+#[derive(Debug, Default)]
+struct ResponseCache {
+    entries: Mutex<HashMap<CacheKey, CachedResponse>>,
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    response: Response,
+    etag: Option<ETag>,
+}
+
+impl CachedResponse {
+    fn respond(&self, req: &Request) -> Response {
+        self.etag
+            .as_ref()
+            .and_then(|etag| Response::conditional(req, etag))
+            .unwrap_or_else(|| self.response.clone_serialized())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    protocol: String,
+    encoding: Option<AcceptedEncoding>,
+    keep_alive: bool,
+}
+
+impl CacheKey {
+    fn for_request(req: &Request) -> CacheKey {
+        CacheKey {
+            protocol: req.protocol.to_string(),
+            encoding: req.accept_encodings.first().cloned(),
+            keep_alive: req.keep_alive(),
+        }
+    }
+}
+
+impl ResponseCache {
+    /// Runs `f` on a miss without holding the lock, so uncached requests
+    /// are still handled concurrently. Racing misses each run `f` and the
+    /// first to finish is kept.
+    fn get_or_insert<F: FnOnce() -> Response>(&self, req: &Request, f: F) -> Response {
+        // The subject cookie is appended once the response is built, which
+        // a serialized response can't take.
+        if req.is_per_subject() {
+            return f();
+        }
+
+        let key: CacheKey = CacheKey::for_request(req);
+
+        match self.entries.lock() {
+            Ok(entries) => {
+                if let Some(cached) = entries.get(&key) {
+                    return cached.respond(req);
+                }
+            }
+            Err(e) => {
+                error!(error = e.to_string(), "Response cache lock poisoned");
+                return f();
+            }
+        }
+
+        let mut res: Response = f();
+
+        if res.is_handler_error() || res.is_streaming() || req.is_per_subject() {
+            return res;
+        }
+
+        let cached: CachedResponse = CachedResponse {
+            etag: res.etag_or_tag(),
+            response: res.clone_serialized(),
+        };
+        let res: Response = cached.respond(req);
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() < MAX_CACHED_VARIANTS {
+                entries.entry(key).or_insert(cached);
+            }
+        }
+
+        res
+    }
 }
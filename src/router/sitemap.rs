@@ -0,0 +1,108 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::http::media_type::MediaType;
+use crate::http::{ContentType, Request, Response, StatusCode};
+
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(3600);
+
+type PathProvider = Box<dyn Fn() -> Vec<String> + Send + Sync + 'static>;
+
+/// A `/sitemap.xml` listing the router's routes marked
+/// [`Route::indexable`](super::route::Route::indexable), built afresh for
+/// every request to it.
+pub struct Sitemap {
+    base_url: String,
+    providers: Vec<PathProvider>,
+    max_age: Duration,
+}
+
+impl Sitemap {
+    /// A sitemap for the site at `base_url`, e.g. `https://example.com`,
+    /// which the listed paths are appended to.
+    pub fn new(base_url: &str) -> Sitemap {
+        Sitemap {
+            base_url: String::from(base_url.trim_end_matches('/')),
+            providers: vec![],
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Also lists the paths `provider` returns when the sitemap is served,
+    /// such as one per blog post behind a `/posts/:slug` route.
+    pub fn provider(
+        &mut self,
+        provider: impl Fn() -> Vec<String> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// How long caches may keep the sitemap, an hour unless set.
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub(super) fn respond(&self, req: &Request, route_paths: Vec<String>) -> Response {
+        let mut xml: String = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+
+        let provided = self.providers.iter().flat_map(|provider| provider());
+        for path in route_paths.into_iter().chain(provided) {
+            let separator: &str = if path.starts_with('/') { "" } else { "/" };
+            let url: String = format!("{}{}{}", self.base_url, separator, path);
+
+            xml.push_str(&format!("  <url><loc>{}</loc></url>\n", escape_xml(&url)));
+        }
+        xml.push_str("</urlset>\n");
+
+        let mut media_type: MediaType = MediaType::new("application", "xml");
+        media_type
+            .params
+            .push((String::from("charset"), String::from("utf-8")));
+
+        let mut res: Response = Response::from_bytes(
+            req,
+            StatusCode::OK,
+            Some(xml.into_bytes()),
+            ContentType::Media(Box::new(media_type)),
+            req.protocol,
+        );
+        res.add_header(
+            "Cache-Control",
+            format!("public, max-age={}", self.max_age.as_secs()),
+        );
+
+        res
+    }
+}
+
+impl fmt::Debug for Sitemap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sitemap")
+            .field("base_url", &self.base_url)
+            .field("providers", &self.providers.len())
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped: String = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
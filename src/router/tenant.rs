@@ -0,0 +1,76 @@
+use crate::http::Request;
+
+use super::{RouteError, Router, VirtualHost, path::Path, route::Route};
+
+/// Where the tenant a request is for is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TenantSource {
+    /// The leftmost label of a `Host` one label under `base`, so `acme` for
+    /// `acme.example.com` with a base of `example.com`.
+    Subdomain { base: String },
+    /// The value of this header.
+    Header(String),
+    /// The first path segment, so `acme` for `/acme/orders`. Each tenant's
+    /// routes are registered under it.
+    PathPrefix,
+}
+
+impl TenantSource {
+    pub(super) fn tenant_of<'a>(&self, req: &'a Request) -> Option<&'a str> {
+        match self {
+            TenantSource::Subdomain { base } => {
+                let host: &str = super::host_name(req)?;
+                let (label, domain) = host.split_once('.')?;
+
+                domain.eq_ignore_ascii_case(base).then_some(label)
+            }
+            TenantSource::Header(name) => req.header_val(name).map(|val| val.trim()),
+            TenantSource::PathPrefix => req.path.first_segment(),
+        }
+    }
+}
+
+/// The tenants registered through [`Router::tenants`], each with its own
+/// routes and context.
+#[derive(Debug)]
+pub struct Tenants<'a, Ctx: Send + Sync> {
+    router: &'a mut Router<Ctx>,
+}
+
+impl<'a, Ctx: Send + Sync> Tenants<'a, Ctx> {
+    pub(super) fn new(router: &'a mut Router<Ctx>) -> Tenants<'a, Ctx> {
+        Tenants { router }
+    }
+
+    /// Dispatches requests for tenant `id` to `router`, whose handlers get
+    /// `ctx` in place of the server-wide context. Per-tenant settings, such
+    /// as a file root or rate limits, belong in `ctx`. Ids are lowercased;
+    /// they match headers and host names in any case, but path prefixes
+    /// only in lowercase.
+    pub fn add(
+        &mut self,
+        id: &str,
+        mut router: Router<Ctx>,
+        ctx: Ctx,
+    ) -> Result<&mut Self, RouteError> {
+        let name: String = id.to_ascii_lowercase();
+
+        if self.router.tenant_source == Some(TenantSource::PathPrefix) {
+            let prefix: Path = Path::parse(&format!("/{}", name))?;
+            let routes: Vec<Route<Ctx>> = std::mem::take(&mut router.routes);
+
+            for route in routes {
+                router.insert_route(route.prefixed(&prefix))?;
+            }
+        }
+
+        self.router.tenants.retain(|tenant| tenant.name != name);
+        self.router.tenants.push(VirtualHost {
+            name,
+            router,
+            ctx: Some(ctx),
+        });
+
+        Ok(self)
+    }
+}
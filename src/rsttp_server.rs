@@ -1,51 +1,227 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::Read;
+use std::io::Write;
 use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 
-use crate::config::Config;
-use crate::http::{Request, Response};
+use crate::access_log::{AccessLog, AccessLogEntry, FlushHook};
+use crate::config::{Config, HttpProtocol, Runtime};
+use crate::conn_limit::{ConnectionLimiter, ConnectionPermit};
+use crate::events::{EventBus, ServerEvent};
+use crate::experiment::Assignment;
+use crate::http::body::{self, BodyError, StreamBuffer};
+use crate::http::{
+    Headers, Hijack, Progress, ProgressCallback, Request, RequestParseError, Response, StatusCode,
+};
+use crate::quota::{QuotaExceeded, QuotaStore, Quotas};
 use crate::router::Router;
+use crate::subsystem::{OnStop, Subsystem, Subsystems};
 use crate::thread_pool::ThreadPool;
+use crate::transfer::TransferAccounting;
+use crate::watchdog::{Activity, Monitor};
+use crate::{affinity, net};
+
+const WRITE_CHUNK_SIZE: usize = 16 * 1024;
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
 
 #[derive(Debug)]
 pub struct RsttpServer<Ctx: Send + Sync + std::fmt::Debug + 'static> {
     pub config: Config<Ctx>,
     pub router: Router<Ctx>,
-    thread_pool: ThreadPool,
+    thread_pool: Option<ThreadPool>,
+    /// Runs handlers of routes with a timeout, off the connection's worker.
+    handler_pool: Option<ThreadPool>,
+    thread_count: usize,
     peer_connections: Mutex<HashMap<SocketAddr, TcpStream>>,
+    connection_limiter: Arc<ConnectionLimiter>,
+    monitor: Arc<Monitor>,
+    events: EventBus,
+    subsystems: Subsystems,
 }
 
 impl<Ctx: Send + Sync + std::fmt::Debug> RsttpServer<Ctx> {
     pub fn new(config: Config<Ctx>, router: Router<Ctx>, thread_count: usize) -> RsttpServer<Ctx> {
+        let thread_pool: Option<ThreadPool> = match config.runtime {
+            Runtime::Pooled => Some(RsttpServer::<Ctx>::worker_pool(&config, thread_count)),
+            Runtime::ThreadPerCore | Runtime::Inline => None,
+        };
+        let handler_pool: Option<ThreadPool> = match config.runtime {
+            Runtime::Pooled | Runtime::ThreadPerCore if router.has_timeouts() => {
+                Some(ThreadPool::new(thread_count, Arc::clone(&config.clock)))
+            }
+            _ => None,
+        };
+
+        let connection_limiter: Arc<ConnectionLimiter> = Arc::new(ConnectionLimiter::new(
+            config.max_connections_per_client,
+            config.trusted_proxies.clone(),
+        ));
+
+        let subsystems: Subsystems = Subsystems::default();
+        if let Some(flush) = RsttpServer::<Ctx>::flush_subsystem(&config) {
+            subsystems.add(Box::new(flush));
+        }
+
         RsttpServer {
             config,
             router,
-            thread_pool: ThreadPool::new(thread_count),
+            thread_pool,
+            handler_pool,
+            thread_count,
             peer_connections: Mutex::new(HashMap::new()),
+            connection_limiter,
+            monitor: Arc::new(Monitor::default()),
+            events: EventBus::default(),
+            subsystems,
         }
     }
 
-    #[instrument]
+    /// The pooled runtime's workers, pinned over `cpu_affinity` when set.
+    fn worker_pool(config: &Config<Ctx>, thread_count: usize) -> ThreadPool {
+        match RsttpServer::<Ctx>::affinity(config) {
+            Some(cores) => ThreadPool::spread(thread_count, cores, Arc::clone(&config.clock)),
+            None => ThreadPool::new(thread_count, Arc::clone(&config.clock)),
+        }
+    }
+
+    /// The cores in `cpu_affinity`, unless none are listed or threads can't
+    /// be pinned here.
+    fn affinity(config: &Config<Ctx>) -> Option<&[usize]> {
+        let cores: &[usize] = config
+            .cpu_affinity
+            .as_deref()
+            .filter(|cores| !cores.is_empty())?;
+
+        if !affinity::SUPPORTED {
+            warn!("CPU affinity is only supported on Linux, leaving threads unpinned");
+            return None;
+        }
+
+        Some(cores)
+    }
+
+    /// The subsystem flushing the configured sinks as the server stops.
+    /// Being registered first, it is stopped after every other subsystem.
+    fn flush_subsystem(config: &Config<Ctx>) -> Option<OnStop> {
+        let access_log: Option<FlushHook> =
+            config.access_log.as_ref().and_then(AccessLog::flush_hook);
+        let accounting: Option<Arc<TransferAccounting>> = config
+            .transfer_accounting
+            .as_ref()
+            .filter(|accounting| accounting.has_flush())
+            .cloned();
+        let quota_store: Option<Arc<dyn QuotaStore>> =
+            config.quotas.as_ref().map(Quotas::store_handle);
+
+        if access_log.is_none() && accounting.is_none() && quota_store.is_none() {
+            return None;
+        }
+
+        Some(OnStop::new("flush", move || {
+            if let Some(flush) = access_log {
+                flush();
+            }
+            if let Some(accounting) = accounting {
+                accounting.flush();
+            }
+            if let Some(store) = quota_store {
+                store.flush();
+            }
+        }))
+    }
+
+    /// Registers a background subsystem to be started when the server starts
+    /// listening, and stopped by [`RsttpServer::stop_subsystems`] or when
+    /// the server is dropped.
+    pub fn add_subsystem(&self, subsystem: impl Subsystem + 'static) {
+        self.subsystems.add(Box::new(subsystem));
+    }
+
+    /// Stops the started subsystems in reverse order of startup, waiting for
+    /// each to finish. The access log, transfer accounting and quota store
+    /// hooks configured when the server was created are flushed last.
+    pub fn stop_subsystems(&self) {
+        self.subsystems.stop_all();
+    }
+
+    /// Returns a receiver of the server's connection and request events.
+    /// Events are dropped for a subscriber once its receiver is dropped.
+    pub fn subscribe(&self) -> Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Serves until the process exits. Returns at once, serving nothing,
+    /// if the config fails [`Config::validate`] or a subsystem fails to
+    /// start.
     pub fn listen(self: Arc<Self>) {
+        if let Err(e) = self.config.validate() {
+            error!(error = e.to_string(), "Refusing to start");
+            return;
+        }
+
+        if let Some(watchdog) = self.config.watchdog {
+            match self.config.runtime {
+                Runtime::Inline => warn!("The watchdog does not run with the inline runtime"),
+                _ => self.add_subsystem(
+                    self.monitor
+                        .subsystem(watchdog, Arc::clone(&self.config.clock)),
+                ),
+            }
+        }
+
+        if self.subsystems.start_all().is_err() {
+            return;
+        }
+
+        match (&self.thread_pool, self.config.runtime) {
+            (Some(thread_pool), Runtime::Pooled) => self.listen_pooled(thread_pool),
+            (_, Runtime::Inline) => self.listen_inline(),
+            _ if !net::reuseport_available() => {
+                warn!("SO_REUSEPORT is unavailable, so thread-per-core falls back to one pool");
+                self.listen_pooled(&RsttpServer::<Ctx>::worker_pool(
+                    &self.config,
+                    self.thread_count,
+                ));
+            }
+            _ => self.listen_per_core(),
+        }
+    }
+
+    #[instrument(skip(thread_pool))]
+    fn listen_pooled(self: &Arc<Self>, thread_pool: &ThreadPool) {
+        let acceptor: Arc<Activity> = Arc::new(Activity::new());
+        self.monitor.watch_acceptor(0, Arc::clone(&acceptor));
+        self.monitor.watch_pool(0, thread_pool.health());
+
         match TcpListener::bind(self.addr_as_string()) {
             Ok(listener) => {
                 for stream in listener.incoming() {
-                    let server: Arc<Self> = Arc::clone(&self);
+                    acceptor.begin(self.config.clock.now());
+                    let server: Arc<Self> = Arc::clone(self);
 
                     match stream {
                         Ok(stream) => {
                             if let Ok(addr) = stream.peer_addr() {
+                                let Some(permit) = self.admit(&stream, addr) else {
+                                    continue;
+                                };
+
                                 if let Ok(mut connections) = self.peer_connections.lock() {
                                     if connections.get(&addr).is_none() {
                                         connections.insert(addr, stream);
                                     }
 
-                                    self.thread_pool.execute(move || {
-                                        server.tcp_event_handler(addr, &server);
+                                    thread_pool.execute(move || {
+                                        server.tcp_event_handler(addr);
+                                        drop(permit);
                                     });
                                 }
                             }
@@ -54,6 +230,7 @@ impl<Ctx: Send + Sync + std::fmt::Debug> RsttpServer<Ctx> {
                             error!(error = e.to_string());
                         }
                     }
+                    acceptor.end();
                 }
             }
             Err(e) => {
@@ -62,110 +239,744 @@ impl<Ctx: Send + Sync + std::fmt::Debug> RsttpServer<Ctx> {
         }
     }
 
-    pub fn addr_as_string(&self) -> String {
-        self.config.addr()
-    }
-
     #[instrument]
-    fn respond(stream: &TcpStream, response: Response) {
-        match response.write_to(stream) {
-            Ok(_) => {
-                info!("successful response");
-            }
-            Err(e) => match e.kind() {
-                std::io::ErrorKind::BrokenPipe => {
-                    error!("Client disconnected during response");
-                }
-                std::io::ErrorKind::ConnectionReset => {
-                    error!("Connection reset by client");
+    fn listen_inline(self: &Arc<Self>) {
+        let listener: TcpListener = match TcpListener::bind(self.addr_as_string()) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(error = e.to_string());
+                return;
+            }
+        };
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let Some(permit) = stream
+                        .peer_addr()
+                        .ok()
+                        .and_then(|addr| self.admit(&stream, addr))
+                    else {
+                        continue;
+                    };
+
+                    self.serve_connection(&stream);
+                    drop(permit);
                 }
-                _ => {
-                    error!(error = e.to_string(), "ERROR: Failed to write response");
+                Err(e) => {
+                    error!(error = e.to_string());
                 }
-            },
+            }
         }
     }
 
     #[instrument]
-    fn tcp_event_handler(&self, socket_addr: SocketAddr, server: &RsttpServer<Ctx>) {
-        let stream = if let Ok(mut connections) = self.peer_connections.lock() {
-            match connections.get(&socket_addr) {
-                Some(stream) => {
-                    if let Err(e) =
-                        stream.set_read_timeout(Some(server.config.persist_connection_for))
-                    {
-                        error!(error = e.to_string(), "Failed to set read timeout");
-                        connections.remove(&socket_addr);
-                        return;
-                    }
-
-                    stream.try_clone().ok()
-                }
-                None => None,
+    fn listen_per_core(self: &Arc<Self>) {
+        let addr: SocketAddr = match self.addr_as_string().parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!(error = ?e, "Invalid listen address");
+                return;
             }
-        } else {
-            error!("Could not obtain lock on peer connections");
-            return;
         };
 
-        let stream = match stream {
-            Some(s) => s,
+        let cores: Vec<usize> = match RsttpServer::<Ctx>::affinity(&self.config) {
+            Some(cores) => cores.to_vec(),
             None => {
-                error!("Could not extract stream handle");
+                let available: usize = thread::available_parallelism()
+                    .map(NonZeroUsize::get)
+                    .unwrap_or(1);
+                (0..available).collect()
+            }
+        };
+        let workers_per_core: usize = (self.thread_count / cores.len()).max(1);
+
+        let acceptors: Vec<JoinHandle<()>> = cores
+            .into_iter()
+            .map(|core| {
+                let server: Arc<Self> = Arc::clone(self);
+                thread::spawn(move || server.accept_on_core(addr, core, workers_per_core))
+            })
+            .collect();
+
+        for acceptor in acceptors {
+            if acceptor.join().is_err() {
+                error!("Acceptor thread panicked");
+            }
+        }
+    }
+
+    fn accept_on_core(self: Arc<Self>, addr: SocketAddr, core: usize, worker_count: usize) {
+        if affinity::SUPPORTED {
+            if let Err(e) = affinity::pin_current_thread(core) {
+                error!(core, error = e.to_string(), "Failed to pin acceptor");
+            }
+        }
+
+        let listener: TcpListener = match net::bind_reuseport(addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(core, error = e.to_string(), "Failed to bind acceptor");
                 return;
             }
         };
 
-        let mut keep_alive: bool = true;
+        let thread_pool: ThreadPool = if affinity::SUPPORTED {
+            ThreadPool::pinned(worker_count, core, Arc::clone(&self.config.clock))
+        } else {
+            ThreadPool::new(worker_count, Arc::clone(&self.config.clock))
+        };
+
+        let acceptor: Arc<Activity> = Arc::new(Activity::new());
+        self.monitor.watch_acceptor(core, Arc::clone(&acceptor));
+        self.monitor.watch_pool(core, thread_pool.health());
+
+        for stream in listener.incoming() {
+            acceptor.begin(self.config.clock.now());
+            match stream {
+                Ok(stream) => {
+                    let Some(permit) = stream
+                        .peer_addr()
+                        .ok()
+                        .and_then(|addr| self.admit(&stream, addr))
+                    else {
+                        continue;
+                    };
+
+                    let server: Arc<Self> = Arc::clone(&self);
+                    thread_pool.execute(move || {
+                        server.serve_connection(&stream);
+                        drop(permit);
+                    });
+                }
+                Err(e) => {
+                    error!(error = e.to_string());
+                }
+            }
+            acceptor.end();
+        }
+    }
+
+    /// Takes a connection slot for the client at `addr`, or answers 429 and
+    /// turns the connection away when the client is at its limit.
+    fn admit(&self, stream: &TcpStream, addr: SocketAddr) -> Option<ConnectionPermit> {
+        let permit: Option<ConnectionPermit> = self.connection_limiter.acquire(addr.ip());
+
+        if permit.is_none() {
+            info!(client = %addr.ip(), "Client is over its connection limit");
+            self.respond(stream, Response::too_many_requests());
+        }
+
+        permit
+    }
+
+    pub fn addr_as_string(&self) -> String {
+        self.config.addr()
+    }
+
+    /// Writes `response` within the configured size and time limits. Returns
+    /// whether the connection can still be used afterwards.
+    fn respond(&self, stream: &TcpStream, response: Response) -> bool {
+        self.write_response(stream, response).0
+    }
+
+    /// Like [`RsttpServer::respond`], also returning how many bytes reached
+    /// the connection.
+    #[instrument(skip(self))]
+    fn write_response(&self, stream: &TcpStream, mut response: Response) -> (bool, usize) {
+        let mut progress: Option<ProgressCallback> = response.take_progress();
+        let mut bytes: Cow<[u8]> = response.bytes();
 
-        while keep_alive {
-            let req = match self.get_request_from_stream(&stream) {
-                Ok(req) => req,
+        if let Some(max) = self.config.max_response_size {
+            if bytes.len() > max {
+                error!(
+                    size = bytes.len(),
+                    max, "Response exceeds the maximum response size"
+                );
+                bytes = Cow::Owned(
+                    Response::default_message(StatusCode::INTERNAL_SERVER_ERROR).to_bytes(),
+                );
+            }
+        }
+
+        let mut sent: usize = 0;
+
+        let written: bool =
+            match self.write_within_deadline(stream, &bytes, progress.as_mut(), &mut sent) {
+                Ok(_) => {
+                    info!("successful response");
+                    true
+                }
                 Err(e) => {
-                    match e {
-                        RequestProcessingError::ConnectionTimeout
-                        | RequestProcessingError::ClientDisconnected => (),
+                    match e.kind() {
+                        std::io::ErrorKind::BrokenPipe => {
+                            error!("Client disconnected during response");
+                        }
+                        std::io::ErrorKind::ConnectionReset => {
+                            error!("Connection reset by client");
+                        }
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                            error!("Response not written within the write timeout");
+                        }
                         _ => {
-                            Self::respond(&stream, Response::bad_request());
+                            error!(error = e.to_string(), "ERROR: Failed to write response");
                         }
-                    };
-                    break;
+                    }
+                    false
                 }
             };
 
-            keep_alive = !req.has_connection_close_header();
+        (written, sent)
+    }
 
-            let response: Response = server.router.handle_request(req, &server.config.ctx);
+    fn write_within_deadline(
+        &self,
+        mut stream: &TcpStream,
+        bytes: &[u8],
+        mut progress: Option<&mut ProgressCallback>,
+        written: &mut usize,
+    ) -> std::io::Result<()> {
+        let deadline: Option<Instant> = self
+            .config
+            .response_write_timeout
+            .map(|timeout| self.config.clock.now() + timeout);
+
+        // Without a deadline or anyone watching, one write is enough.
+        let chunk_size: usize = match (deadline, &progress) {
+            (None, None) => bytes.len().max(1),
+            _ => WRITE_CHUNK_SIZE,
+        };
 
-            Self::respond(&stream, response);
+        let started: Instant = self.config.clock.now();
+
+        for chunk in bytes.chunks(chunk_size) {
+            if let Some(deadline) = deadline {
+                let remaining: Duration =
+                    deadline.saturating_duration_since(self.config.clock.now());
+                if remaining.is_zero() {
+                    return Err(std::io::ErrorKind::TimedOut.into());
+                }
+
+                stream.set_write_timeout(Some(remaining))?;
+            }
+
+            stream.write_all(chunk)?;
+            *written += chunk.len();
+
+            if let Some(progress) = progress.as_mut() {
+                progress.report(Progress {
+                    written: *written,
+                    total: bytes.len(),
+                    elapsed: self.elapsed_since(started),
+                });
+            }
+        }
+
+        if deadline.is_some() {
+            stream.set_write_timeout(None)?;
         }
 
+        stream.flush()
+    }
+
+    #[instrument]
+    fn tcp_event_handler(self: &Arc<Self>, socket_addr: SocketAddr) {
+        let stream = if let Ok(connections) = self.peer_connections.lock() {
+            match connections.get(&socket_addr) {
+                Some(stream) => stream.try_clone().ok(),
+                None => None,
+            }
+        } else {
+            error!("Could not obtain lock on peer connections");
+            return;
+        };
+
+        match stream {
+            Some(stream) => self.serve_connection(&stream),
+            None => error!("Could not extract stream handle"),
+        };
+
         if let Ok(mut connections) = self.peer_connections.lock() {
             connections.remove(&socket_addr);
         }
     }
 
+    fn serve_connection(self: &Arc<Self>, stream: &TcpStream) {
+        let client: Option<SocketAddr> = stream.peer_addr().ok();
+
+        if let Some(client) = client {
+            self.events
+                .publish(|| ServerEvent::ConnectionOpened { client });
+        }
+
+        self.serve_requests(stream);
+
+        if let Some(client) = client {
+            self.events
+                .publish(|| ServerEvent::ConnectionClosed { client });
+        }
+    }
+
+    fn serve_requests(self: &Arc<Self>, stream: &TcpStream) {
+        if let Err(e) = stream.set_read_timeout(Some(self.config.persist_connection_for)) {
+            error!(error = e.to_string(), "Failed to set read timeout");
+            return;
+        }
+
+        if let Err(e) = stream.set_nodelay(self.config.tcp_nodelay) {
+            error!(error = e.to_string(), "Failed to set TCP_NODELAY");
+        }
+
+        let mut buffer: StreamBuffer<&TcpStream> = StreamBuffer::new(stream);
+
+        loop {
+            #[cfg(feature = "alloc-tracking")]
+            let keep_alive: bool = self.serve_request_tracked(stream, &mut buffer);
+
+            #[cfg(not(feature = "alloc-tracking"))]
+            let keep_alive: bool = self.serve_request(stream, &mut buffer);
+
+            if !keep_alive {
+                break;
+            }
+        }
+    }
+
+    fn serve_request(
+        self: &Arc<Self>,
+        stream: &TcpStream,
+        buffer: &mut StreamBuffer<&TcpStream>,
+    ) -> bool {
+        let consumed_before: usize = buffer.consumed();
+        let mut protocol: Option<HttpProtocol> = None;
+        let mut req = match self.get_request_from_stream(stream, buffer, &mut protocol) {
+            Ok(req) => req,
+            Err(e) => {
+                let response: Option<Response> = match e {
+                    RequestProcessingError::ConnectionTimeout
+                    | RequestProcessingError::ClientDisconnected => None,
+                    RequestProcessingError::UnknownExpectation(_) => {
+                        Some(Response::expectation_failed())
+                    }
+                    RequestProcessingError::HeadTimeout => Some(Response::request_timeout()),
+                    RequestProcessingError::UriTooLong => Some(Response::uri_too_long()),
+                    // There is no version of the client's to answer in.
+                    RequestProcessingError::UnsupportedVersion(_) => {
+                        protocol = None;
+                        Some(Response::http_version_not_supported())
+                    }
+                    RequestProcessingError::UnsupportedTransferEncoding(_) => {
+                        Some(Response::not_implemented())
+                    }
+                    RequestProcessingError::HeadersTooLarge => {
+                        Some(Response::request_header_fields_too_large())
+                    }
+                    RequestProcessingError::BodyTooLarge => Some(Response::payload_too_large()),
+                    _ => {
+                        self.events.publish(|| ServerEvent::Error {
+                            client: stream.peer_addr().ok(),
+                            message: e.to_string(),
+                        });
+                        Some(Response::bad_request())
+                    }
+                };
+
+                if let Some(mut response) = response {
+                    if let Some(protocol) = protocol {
+                        response.set_protocol(protocol);
+                    }
+                    self.respond(stream, response);
+                }
+                return false;
+            }
+        };
+
+        let mut keep_alive: bool = req.keep_alive();
+        let protocol: HttpProtocol = req.protocol;
+        let is_http10: bool = matches!(protocol, HttpProtocol::Http10);
+        let accepts_utf8: bool = req.preferred_charset(&["utf-8"]).is_some();
+        let target: Option<(String, String)> = self
+            .events
+            .has_subscribers()
+            .then(|| (req.req_type.to_string(), req.path.encoded()));
+        let started: Instant = self.config.clock.now();
+        let log_entry: Option<AccessLogEntry> = self
+            .config
+            .access_log
+            .as_ref()
+            .map(|log| log.begin(&req, stream.peer_addr().ok()));
+        let transfer: Option<(Option<String>, Option<String>)> = self
+            .config
+            .transfer_accounting
+            .as_ref()
+            .map(|accounting| (self.router.pattern_for(&req), accounting.client_of(&req)));
+        let received: usize = buffer.consumed() - consumed_before;
+        let quota_client: Option<String> = self
+            .config
+            .quotas
+            .as_ref()
+            .and_then(|quotas| quotas.client_of(&req));
+        let exceeded: Option<QuotaExceeded> = match (&self.config.quotas, &quota_client) {
+            (Some(quotas), Some(client)) => quotas.check(client, self.config.clock.system_time()),
+            _ => None,
+        };
+        let set_cookie: Option<String> = self.config.experiments.as_ref().and_then(|experiments| {
+            let assignment: Assignment = experiments.assign(&req);
+            req.set_variants(assignment.variants, assignment.set_cookie.is_some());
+            assignment.set_cookie
+        });
+        let variant_read: Arc<AtomicBool> = req.variant_read();
+
+        let mut response: Response = if let Some(exceeded) = &exceeded {
+            info!(client = quota_client, %exceeded, "Client is over its quota");
+            Response::quota_exceeded(exceeded)
+        } else if self.config.accepts_method(&req.req_type)
+            || self.router.has_routes_for(&req.req_type)
+        {
+            self.route_request(req)
+        } else {
+            Response::not_implemented()
+        };
+
+        if self.config.strict_charset && !accepts_utf8 && response.is_text() {
+            response = Response::not_acceptable();
+        }
+        response.set_protocol(protocol);
+
+        if let Some(cookie) = set_cookie {
+            response.append_header("Set-Cookie", cookie);
+        }
+        if let Some(experiments) = &self.config.experiments {
+            if variant_read.load(Ordering::Relaxed) {
+                response.vary_by_variant(experiments.cookie_name());
+            }
+        }
+
+        let status: u16 = response.code().as_u16();
+
+        if is_http10 && keep_alive {
+            keep_alive = response.keep_alive();
+        }
+        let hijack: Option<Hijack> = response.take_hijack();
+
+        let (written, sent) = self.write_response(stream, response);
+
+        if let (Some(accounting), Some((route, client))) =
+            (&self.config.transfer_accounting, transfer)
+        {
+            accounting.record(route, client, received, sent);
+        }
+
+        if let (Some(quotas), Some(client), None) = (&self.config.quotas, quota_client, exceeded) {
+            quotas.record(&client, self.config.clock.system_time(), received, sent);
+        }
+
+        if let (Some(log), Some(entry)) = (&self.config.access_log, log_entry) {
+            log.finish(entry, status, self.elapsed_since(started));
+        }
+
+        if let Some((method, path)) = target {
+            self.events.publish(|| ServerEvent::RequestCompleted {
+                client: stream.peer_addr().ok(),
+                method,
+                path,
+                status,
+                duration: self.elapsed_since(started),
+            });
+        }
+
+        if !written {
+            self.events.publish(|| ServerEvent::Error {
+                client: stream.peer_addr().ok(),
+                message: String::from("Failed to write response"),
+            });
+        }
+
+        if let Some(hijack) = hijack {
+            if written && stream.set_read_timeout(None).is_ok() {
+                hijack.run(stream);
+            }
+
+            return false;
+        }
+
+        written && keep_alive
+    }
+
+    fn elapsed_since(&self, earlier: Instant) -> Duration {
+        self.config.clock.now().saturating_duration_since(earlier)
+    }
+
+    /// Routes `req`, running the handler on the handler pool when the route
+    /// has a timeout so that a handler overrunning it does not hold up the
+    /// connection's worker. With the pool busy, the request is answered
+    /// with 503.
+    fn route_request(self: &Arc<Self>, req: Request) -> Response {
+        let timed: Option<(Duration, &ThreadPool)> = self
+            .handler_pool
+            .as_ref()
+            .and_then(|pool| Some((self.router.timeout_for(&req)?, pool)));
+
+        let Some((timeout, pool)) = timed else {
+            return self.router.handle_request(req, &self.config.ctx);
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let server: Arc<Self> = Arc::clone(self);
+        let accepted: bool = pool.try_execute(move || {
+            let _ = sender.send(server.router.handle_request(req, &server.config.ctx));
+        });
+
+        if !accepted {
+            warn!("No handler thread free for a route with a timeout");
+            return Response::service_unavailable();
+        }
+
+        match receiver.recv_timeout(timeout) {
+            Ok(response) => response,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(?timeout, "Handler exceeded its route timeout");
+                Response::service_unavailable()
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("Handler panicked");
+                Response::default_message(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc-tracking")]
+    fn serve_request_tracked(
+        self: &Arc<Self>,
+        stream: &TcpStream,
+        buffer: &mut StreamBuffer<&TcpStream>,
+    ) -> bool {
+        let (keep_alive, stats) =
+            crate::alloc_tracking::measure(|| self.serve_request(stream, buffer));
+
+        if let Some(budget) = self.config.allocation_budget {
+            if let Err(e) = budget.check(&stats) {
+                warn!(
+                    error = e.to_string(),
+                    "Request exceeded its allocation budget"
+                );
+            }
+        }
+
+        keep_alive
+    }
+
+    /// Reads the next request. `protocol` is set to the version on its
+    /// request line as soon as that has arrived, for an error response to
+    /// be sent in.
     fn get_request_from_stream(
         &self,
         mut stream: &TcpStream,
+        buffer: &mut StreamBuffer<&TcpStream>,
+        protocol: &mut Option<HttpProtocol>,
     ) -> Result<Request, RequestProcessingError> {
-        let mut read_data: [u8; 8192] = [0; 8192];
-        let bytes_read: usize = match stream.read(&mut read_data) {
-            Ok(0) => return Err(RequestProcessingError::ClientDisconnected),
-            Ok(n) => n,
-            Err(e) => match e.kind() {
-                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
-                    return Err(RequestProcessingError::ConnectionTimeout);
-                }
-                _ => return Err(RequestProcessingError::UnknownIOError),
-            },
+        let head: Vec<u8> = match buffer.read_head(
+            self.config.max_header_bytes,
+            self.config.header_read_timeout,
+            self.config.lenient_parsing,
+            self.config.clock.as_ref(),
+        ) {
+            Ok(head) => head.ok_or(RequestProcessingError::ClientDisconnected)?,
+            Err(e) => {
+                *protocol = request_line_protocol(buffer.unread());
+                return Err(e.into());
+            }
+        };
+        *protocol = request_line_protocol(&head);
+
+        let target_len: usize = head
+            .split(|b| *b == b'\r' || *b == b'\n')
+            .next()
+            .and_then(|line| line.split(|b| *b == b' ').nth(1))
+            .map_or(0, |target| target.len());
+        if target_len > self.config.max_target_len {
+            return Err(RequestProcessingError::UriTooLong);
+        }
+
+        let parsed: Result<Request, RequestParseError> = match self.config.lenient_parsing {
+            true => Request::new_lenient(&head),
+            false => Request::new(&head),
+        };
+        let mut req: Request = parsed.map_err(|e| match e {
+            RequestParseError::UnsupportedVersion(version) => {
+                RequestProcessingError::UnsupportedVersion(version)
+            }
+            e => RequestProcessingError::RequestParsingError(e.to_string()),
+        })?;
+
+        if req.headers.len() > self.config.max_header_count {
+            return Err(RequestProcessingError::HeadersTooLarge);
+        }
+
+        if let Ok(peer) = stream.peer_addr() {
+            req.set_peer(peer, &self.config.trusted_proxies);
+        }
+
+        if let Some(flags) = &self.config.feature_flags {
+            req.set_flags(Arc::clone(flags));
+        }
+
+        let framing: BodyFraming = body_framing(&req, self.config.max_body_size)?;
+
+        // The body is read only after any expectation is met, so a client
+        // waiting on `100-continue` doesn't send it before being told to.
+        // HTTP/1.0 has no interim responses, so such clients just send it.
+        if let Some(expectation) = req.header_val("Expect") {
+            if !expectation.trim().eq_ignore_ascii_case("100-continue") {
+                return Err(RequestProcessingError::UnknownExpectation(
+                    expectation.clone(),
+                ));
+            }
+
+            if matches!(req.protocol, HttpProtocol::Http11) {
+                stream
+                    .write_all(CONTINUE)
+                    .map_err(|_| RequestProcessingError::ClientDisconnected)?;
+            }
+        }
+
+        let (body, trailers) = match framing {
+            BodyFraming::Chunked => {
+                let max_trailers: usize = self
+                    .config
+                    .max_header_count
+                    .saturating_sub(req.headers.len());
+                buffer.read_chunked(self.config.max_body_size, max_trailers)?
+            }
+            BodyFraming::Length(len) => (buffer.read_exact_bytes(len)?, Headers::new()),
+            BodyFraming::Empty => (Vec::new(), Headers::new()),
         };
 
-        let read_data: &str = std::str::from_utf8(&read_data[..bytes_read])?;
+        req.body = body;
+        req.trailers = trailers;
+
+        if let Some(max_len) = self.config.max_decompressed_body_size {
+            decode_body(&mut req, max_len)?;
+        }
+
+        Ok(req)
+    }
+}
+
+/// Works out how the body of `req` is delimited, rejecting a declared
+/// length over `max_body_size` before any of it is read.
+///
+/// Framing that could be read two ways, such as both `Content-Length`
+/// and `Transfer-Encoding` or disagreeing lengths, is rejected outright:
+/// a proxy in front may have picked the other reading, and the rest of
+/// the connection would then be out of step with it.
+fn body_framing(
+    req: &Request,
+    max_body_size: Option<usize>,
+) -> Result<BodyFraming, RequestProcessingError> {
+    let codings: Vec<&String> = req.header_all("Transfer-Encoding");
+    let lengths: Vec<&String> = req.header_all("Content-Length");
+
+    if !codings.is_empty() && !lengths.is_empty() {
+        return Err(RequestProcessingError::AmbiguousFraming(
+            "both Content-Length and Transfer-Encoding",
+        ));
+    }
+
+    if !codings.is_empty() {
+        let codings: Vec<&str> = codings
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+            .collect();
+        let chunked: usize = codings
+            .iter()
+            .filter(|coding| coding.eq_ignore_ascii_case("chunked"))
+            .count();
+
+        if chunked > 1 {
+            return Err(RequestProcessingError::AmbiguousFraming(
+                "chunked applied more than once",
+            ));
+        }
+
+        if chunked == 0 || codings.len() > 1 {
+            return Err(RequestProcessingError::UnsupportedTransferEncoding(
+                codings.join(", "),
+            ));
+        }
+
+        return Ok(BodyFraming::Chunked);
+    }
+
+    let mut len: Option<usize> = None;
+
+    for value in lengths.iter().flat_map(|value| value.split(',')) {
+        let value: &str = value.trim();
+        let parsed: usize = match value.bytes().all(|b| b.is_ascii_digit()) {
+            true => value.parse().ok(),
+            false => None,
+        }
+        .ok_or_else(|| {
+            RequestProcessingError::RequestParsingError(String::from("Invalid Content-Length"))
+        })?;
+
+        if len.is_some_and(|len| len != parsed) {
+            return Err(RequestProcessingError::AmbiguousFraming(
+                "conflicting Content-Length values",
+            ));
+        }
+
+        len = Some(parsed);
+    }
+
+    let Some(len) = len else {
+        return Ok(BodyFraming::Empty);
+    };
 
-        Request::new(read_data)
-            .map_err(|e| RequestProcessingError::RequestParsingError(e.to_string()))
+    if max_body_size.is_some_and(|max| len > max) {
+        return Err(RequestProcessingError::BodyTooLarge);
     }
+
+    Ok(BodyFraming::Length(len))
+}
+
+/// The version on the request line at the start of `head`, if the line is
+/// complete and names one.
+fn request_line_protocol(head: &[u8]) -> Option<HttpProtocol> {
+    let end: usize = head.iter().position(|b| *b == b'\n')?;
+    let line: &str = std::str::from_utf8(&head[..end]).ok()?;
+
+    line.trim_end_matches('\r').rsplit(' ').next()?.parse().ok()
+}
+
+/// Replaces a gzip-encoded body with its decoded bytes, updating the headers
+/// to describe them. Bodies in other codings are left as they are.
+fn decode_body(req: &mut Request, max_len: usize) -> Result<(), RequestProcessingError> {
+    let is_gzip: bool = req.header_val("Content-Encoding").is_some_and(|coding| {
+        let coding: &str = coding.trim();
+        coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip")
+    });
+
+    if !is_gzip || req.body.is_empty() {
+        return Ok(());
+    }
+
+    req.body = body::gunzip(&req.body, max_len)?;
+    req.headers.remove("Content-Encoding");
+    if req.headers.contains("Content-Length") {
+        req.headers
+            .insert("Content-Length", req.body.len().to_string());
+    }
+
+    Ok(())
+}
+
+enum BodyFraming {
+    Chunked,
+    Length(usize),
+    Empty,
 }
 
 #[derive(Error, Debug)]
@@ -179,9 +990,165 @@ enum RequestProcessingError {
     #[error("Unknown IO error")]
     UnknownIOError,
 
-    #[error("Failure to convert bytes to string")]
-    UnableToConvertBytesToString(#[from] std::str::Utf8Error),
-
     #[error("Failed to parse request: {0}")]
     RequestParsingError(String),
+
+    #[error("Unsupported transfer coding: {0}")]
+    UnsupportedTransferEncoding(String),
+
+    #[error("Ambiguous message framing: {0}")]
+    AmbiguousFraming(&'static str),
+
+    #[error("Unknown expectation: {0}")]
+    UnknownExpectation(String),
+
+    #[error("Request head was not received in time")]
+    HeadTimeout,
+
+    #[error("Request target is too long")]
+    UriTooLong,
+
+    #[error("Unsupported HTTP version: {0}")]
+    UnsupportedVersion(String),
+
+    #[error("Request header fields are too large")]
+    HeadersTooLarge,
+
+    #[error("Request body is too large")]
+    BodyTooLarge,
+
+    #[error("Malformed request body: {0}")]
+    MalformedBody(String),
+}
+
+impl From<BodyError> for RequestProcessingError {
+    fn from(e: BodyError) -> Self {
+        match e {
+            BodyError::Io(e) => match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                    RequestProcessingError::ConnectionTimeout
+                }
+                std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::BrokenPipe => RequestProcessingError::ClientDisconnected,
+                _ => RequestProcessingError::UnknownIOError,
+            },
+            BodyError::HeadTooLarge => RequestProcessingError::HeadersTooLarge,
+            BodyError::HeadTimeout => RequestProcessingError::HeadTimeout,
+            BodyError::RequestLineTooLong => RequestProcessingError::UriTooLong,
+            BodyError::BodyTooLarge => RequestProcessingError::BodyTooLarge,
+            BodyError::TooManyTrailers => RequestProcessingError::HeadersTooLarge,
+            e => RequestProcessingError::MalformedBody(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framing(headers: &str) -> Result<BodyFraming, RequestProcessingError> {
+        let head: String = format!("POST / HTTP/1.1\r\nHost: a\r\n{}\r\n", headers);
+        let req: Request = Request::new(head.as_bytes()).unwrap();
+
+        body_framing(&req, Some(100))
+    }
+
+    fn is_ambiguous(framing: Result<BodyFraming, RequestProcessingError>) -> bool {
+        matches!(framing, Err(RequestProcessingError::AmbiguousFraming(_)))
+    }
+
+    #[test]
+    fn reads_the_declared_framing() {
+        assert!(matches!(framing(""), Ok(BodyFraming::Empty)));
+        assert!(matches!(
+            framing("Content-Length: 5\r\n"),
+            Ok(BodyFraming::Length(5))
+        ));
+        assert!(matches!(
+            framing("Content-Length: 5\r\nContent-Length: 5\r\n"),
+            Ok(BodyFraming::Length(5))
+        ));
+        assert!(matches!(
+            framing("Transfer-Encoding: Chunked\r\n"),
+            Ok(BodyFraming::Chunked)
+        ));
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        assert!(is_ambiguous(framing(
+            "Content-Length: 5\r\nTransfer-Encoding: chunked\r\n"
+        )));
+        assert!(is_ambiguous(framing(
+            "Transfer-Encoding: chunked\r\nContent-Length: 0\r\n"
+        )));
+    }
+
+    #[test]
+    fn rejects_chunked_applied_twice() {
+        assert!(is_ambiguous(framing(
+            "Transfer-Encoding: chunked, chunked\r\n"
+        )));
+        assert!(is_ambiguous(framing(
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n"
+        )));
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        assert!(is_ambiguous(framing("Content-Length: 5, 6\r\n")));
+        assert!(is_ambiguous(framing(
+            "Content-Length: 5\r\nContent-Length: 6\r\n"
+        )));
+    }
+
+    #[test]
+    fn rejects_unsupported_codings() {
+        for coding in ["gzip", "gzip, chunked", "chunked, gzip"] {
+            assert!(matches!(
+                framing(&format!("Transfer-Encoding: {}\r\n", coding)),
+                Err(RequestProcessingError::UnsupportedTransferEncoding(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_content_length() {
+        for len in ["-1", "+5", "0x5", "5 5", ""] {
+            assert!(matches!(
+                framing(&format!("Content-Length: {}\r\n", len)),
+                Err(RequestProcessingError::RequestParsingError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn protocol_is_read_from_a_complete_request_line() {
+        let protocol = |head: &[u8]| request_line_protocol(head).map(|p| p.to_string());
+
+        assert_eq!(
+            protocol(b"GET / HTTP/1.0\r\nHost: a"),
+            Some(String::from("HTTP/1.0"))
+        );
+        assert_eq!(
+            protocol(b"GET / HTTP/1.1\n"),
+            Some(String::from("HTTP/1.1"))
+        );
+        assert_eq!(protocol(b"GET / HTTP/1.0"), None);
+        assert_eq!(protocol(b"GET / HTTP/2.0\r\n"), None);
+        assert_eq!(protocol(b"GARBAGE\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_declared_length_over_the_limit() {
+        assert!(matches!(
+            framing("Content-Length: 101\r\n"),
+            Err(RequestProcessingError::BodyTooLarge)
+        ));
+        assert!(matches!(
+            framing("Content-Length: 100\r\n"),
+            Ok(BodyFraming::Length(100))
+        ));
+    }
 }
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tracing::{error, info};
+
+/// A background service whose lifetime the server manages. Subsystems are
+/// started in the order they were added when the server starts listening,
+/// and stopped in reverse order when it shuts down or is dropped.
+pub trait Subsystem: Send {
+    fn name(&self) -> &str;
+
+    fn start(&mut self) -> io::Result<()>;
+
+    /// Stops the subsystem and waits for its threads to finish.
+    fn stop(&mut self);
+}
+
+/// The subsystems registered with a server, and how many of them are
+/// running.
+#[derive(Default)]
+pub(crate) struct Subsystems {
+    state: Mutex<(Vec<Box<dyn Subsystem>>, usize)>,
+}
+
+impl Subsystems {
+    pub(crate) fn add(&self, subsystem: Box<dyn Subsystem>) {
+        if let Ok(mut state) = self.state.lock() {
+            state.0.push(subsystem);
+        }
+    }
+
+    /// Starts the subsystems not yet running, in order. If one fails to
+    /// start, the ones already running are stopped again.
+    pub(crate) fn start_all(&self) -> io::Result<()> {
+        let Ok(mut state) = self.state.lock() else {
+            return Err(io::Error::other("subsystem lock poisoned"));
+        };
+        let (subsystems, started) = &mut *state;
+
+        while *started < subsystems.len() {
+            let subsystem: &mut Box<dyn Subsystem> = &mut subsystems[*started];
+
+            if let Err(e) = subsystem.start() {
+                error!(
+                    subsystem = subsystem.name(),
+                    error = e.to_string(),
+                    "Failed to start subsystem"
+                );
+                Subsystems::stop_started(subsystems, started);
+                return Err(e);
+            }
+
+            info!(subsystem = subsystem.name(), "Started subsystem");
+            *started += 1;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn stop_all(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            let (subsystems, started) = &mut *state;
+            Subsystems::stop_started(subsystems, started);
+        }
+    }
+
+    fn stop_started(subsystems: &mut [Box<dyn Subsystem>], started: &mut usize) {
+        while *started > 0 {
+            *started -= 1;
+            let subsystem: &mut Box<dyn Subsystem> = &mut subsystems[*started];
+
+            subsystem.stop();
+            info!(subsystem = subsystem.name(), "Stopped subsystem");
+        }
+    }
+}
+
+impl Drop for Subsystems {
+    fn drop(&mut self) {
+        self.stop_all();
+    }
+}
+
+impl fmt::Debug for Subsystems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = match self.state.lock() {
+            Ok(state) => state.0.iter().map(|s| String::from(s.name())).collect(),
+            Err(_) => vec![],
+        };
+
+        f.debug_struct("Subsystems").field("names", &names).finish()
+    }
+}
+
+/// A subsystem running `task` every `interval` on its own thread, e.g. a
+/// cache or session sweeper.
+pub struct Periodic {
+    name: String,
+    interval: Duration,
+    task: Option<Box<dyn FnMut() + Send + 'static>>,
+    worker: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Periodic {
+    pub fn new(name: &str, interval: Duration, task: impl FnMut() + Send + 'static) -> Periodic {
+        Periodic {
+            name: String::from(name),
+            interval,
+            task: Some(Box::new(task)),
+            worker: None,
+        }
+    }
+}
+
+impl Subsystem for Periodic {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        let Some(mut task) = self.task.take() else {
+            return Err(io::Error::other("periodic subsystem already started"));
+        };
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let interval: Duration = self.interval;
+
+        let handle: JoinHandle<()> =
+            thread::Builder::new()
+                .name(self.name.clone())
+                .spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                        task();
+                    }
+                })?;
+
+        self.worker = Some((stop, handle));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some((stop, handle)) = self.worker.take() {
+            let _ = stop.send(());
+
+            if handle.join().is_err() {
+                error!(subsystem = self.name, "Subsystem thread panicked");
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Periodic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Periodic")
+            .field("name", &self.name)
+            .field("interval", &self.interval)
+            .field("running", &self.worker.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A subsystem running `task` once when stopped, e.g. to flush a buffered
+/// sink as the server shuts down.
+pub struct OnStop {
+    name: String,
+    task: Option<Box<dyn FnOnce() + Send + 'static>>,
+}
+
+impl OnStop {
+    pub fn new(name: &str, task: impl FnOnce() + Send + 'static) -> OnStop {
+        OnStop {
+            name: String::from(name),
+            task: Some(Box::new(task)),
+        }
+    }
+}
+
+impl Subsystem for OnStop {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task();
+        }
+    }
+}
+
+impl fmt::Debug for OnStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnStop")
+            .field("name", &self.name)
+            .field("stopped", &self.task.is_none())
+            .finish_non_exhaustive()
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
+    panic::{self, AssertUnwindSafe},
     sync::{
         Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
         mpsc::{self, Receiver, Sender},
     },
     thread,
@@ -8,33 +10,101 @@ use std::{
 
 use tracing::{error, info, instrument};
 
+use crate::affinity;
+use crate::clock::Clock;
+use crate::watchdog::PoolHealth;
+
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
 #[derive(Debug)]
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Sender<Message>,
+    health: Arc<PoolHealth>,
+    /// Jobs taken by [`ThreadPool::try_execute`] that are queued or running.
+    in_flight: Arc<AtomicUsize>,
 }
 
 impl ThreadPool {
-    pub fn new(thread_count: usize) -> ThreadPool {
+    /// Workers read `clock` for when each job starts.
+    pub fn new(thread_count: usize, clock: Arc<dyn Clock>) -> ThreadPool {
+        ThreadPool::with_affinity(thread_count, None, clock)
+    }
+
+    pub fn pinned(thread_count: usize, core: usize, clock: Arc<dyn Clock>) -> ThreadPool {
+        ThreadPool::with_affinity(thread_count, Some(&[core]), clock)
+    }
+
+    /// Pins worker `i` to `cores[i % cores.len()]`.
+    pub fn spread(thread_count: usize, cores: &[usize], clock: Arc<dyn Clock>) -> ThreadPool {
+        let cores: Option<&[usize]> = Some(cores).filter(|cores| !cores.is_empty());
+
+        ThreadPool::with_affinity(thread_count, cores, clock)
+    }
+
+    fn with_affinity(
+        thread_count: usize,
+        cores: Option<&[usize]>,
+        clock: Arc<dyn Clock>,
+    ) -> ThreadPool {
         assert!(thread_count > 0, "A positive number of threads must exist");
 
         let (sender, receiver) = mpsc::channel();
         let receiver: Arc<Mutex<Receiver<Message>>> = Arc::new(Mutex::new(receiver));
+        let health: Arc<PoolHealth> = Arc::new(PoolHealth::new(thread_count));
 
         ThreadPool {
             workers: (0..thread_count)
-                .map(|i| Worker::new(i, Arc::clone(&receiver)))
+                .map(|i| {
+                    let core: Option<usize> = cores.map(|cores| cores[i % cores.len()]);
+                    Worker::new(
+                        i,
+                        Arc::clone(&receiver),
+                        Arc::clone(&health),
+                        core,
+                        Arc::clone(&clock),
+                    )
+                })
                 .collect(),
             sender,
+            health,
+            in_flight: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     pub fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
         let job = Box::new(f);
+        self.health.queued.fetch_add(1, Ordering::Relaxed);
         let _ = self.sender.send(Message::NewJob(job));
     }
+
+    /// Runs `f` only if it would not have to wait for a worker, and returns
+    /// whether it was taken. A panic in `f` is caught so the worker lives
+    /// on.
+    pub fn try_execute<F: FnOnce() + Send + 'static>(&self, f: F) -> bool {
+        let reserved: bool = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |jobs| {
+                (jobs < self.workers.len()).then_some(jobs + 1)
+            })
+            .is_ok();
+
+        if !reserved {
+            return false;
+        }
+
+        let in_flight: Arc<AtomicUsize> = Arc::clone(&self.in_flight);
+        self.execute(move || {
+            let _ = panic::catch_unwind(AssertUnwindSafe(f));
+            in_flight.fetch_sub(1, Ordering::AcqRel);
+        });
+
+        true
+    }
+
+    pub(crate) fn health(&self) -> Arc<PoolHealth> {
+        Arc::clone(&self.health)
+    }
 }
 
 impl Drop for ThreadPool {
@@ -61,16 +131,36 @@ struct Worker {
 }
 
 impl Worker {
-    #[instrument]
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Message>>>) -> Worker {
+    #[instrument(skip(health, clock))]
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<Receiver<Message>>>,
+        health: Arc<PoolHealth>,
+        core: Option<usize>,
+        clock: Arc<dyn Clock>,
+    ) -> Worker {
         Worker {
             id,
             spawned_thread: thread::spawn(move || {
+                if let Some(core) = core {
+                    if let Err(e) = affinity::pin_current_thread(core) {
+                        error!(
+                            worker_id = id,
+                            core,
+                            error = e.to_string(),
+                            "Failed to pin worker"
+                        );
+                    }
+                }
+
                 loop {
                     let res = match receiver.lock() {
                         Ok(locked_mutex) => match locked_mutex.recv() {
                             Ok(msg) => match msg {
-                                Message::NewJob(job) => Ok(job),
+                                Message::NewJob(job) => {
+                                    health.queued.fetch_sub(1, Ordering::Relaxed);
+                                    Ok(job)
+                                }
                                 Message::Terminate => break,
                             },
                             Err(e) => {
@@ -86,7 +176,10 @@ impl Worker {
                     };
 
                     if let Ok(job) = res {
+                        health.workers[id].begin(clock.now());
                         job();
+                        health.workers[id].end();
+                        health.completed.fetch_add(1, Ordering::Relaxed);
                     }
                 }
             }),
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::ops::AddAssign;
+use std::sync::Mutex;
+
+use crate::http::Request;
+
+/// What requests are grouped by when accounting per client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientIdentity {
+    /// `Request::client_addr`.
+    Ip,
+    /// The value of this header, e.g. an API key. Requests without it are
+    /// counted under their IP.
+    Header(String),
+}
+
+impl ClientIdentity {
+    /// Who `req` is counted against, if it can be told.
+    pub(crate) fn of(&self, req: &Request) -> Option<String> {
+        let header: Option<&String> = match self {
+            ClientIdentity::Ip => None,
+            ClientIdentity::Header(name) => req.header_val(name),
+        };
+
+        header
+            .cloned()
+            .or_else(|| req.client_addr().map(|addr| addr.to_string()))
+    }
+}
+
+/// Bytes moved for a set of requests. Received bytes are as read off the
+/// connection, the head and any chunk framing included; sent bytes are the
+/// written response, head included, but not what a hijack writes after it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Transfer {
+    pub requests: u64,
+    pub received: u64,
+    pub sent: u64,
+}
+
+impl Transfer {
+    /// One request that moved `received` and `sent` bytes.
+    pub fn request(received: usize, sent: usize) -> Transfer {
+        Transfer {
+            requests: 1,
+            received: received as u64,
+            sent: sent as u64,
+        }
+    }
+
+    /// Bytes moved either way.
+    pub fn bytes(&self) -> u64 {
+        self.received + self.sent
+    }
+}
+
+impl AddAssign for Transfer {
+    fn add_assign(&mut self, other: Transfer) {
+        self.requests += other.requests;
+        self.received += other.received;
+        self.sent += other.sent;
+    }
+}
+
+/// Totals by route pattern (e.g. `/files/:path`) and by client.
+#[derive(Debug, Clone, Default)]
+pub struct TransferTotals {
+    pub routes: HashMap<String, Transfer>,
+    pub clients: HashMap<String, Transfer>,
+}
+
+/// Counts the bytes each route and each client transfers.
+///
+/// Totals grow until taken with [`TransferAccounting::take`], so to
+/// aggregate by period, take them from a [`crate::subsystem::Periodic`]
+/// at the end of each one. Requests no route matched are counted per
+/// client only.
+pub struct TransferAccounting {
+    identity: ClientIdentity,
+    totals: Mutex<TransferTotals>,
+    flush: Option<Box<dyn Fn(TransferTotals) + Send + Sync + 'static>>,
+}
+
+impl TransferAccounting {
+    pub fn new(identity: ClientIdentity) -> TransferAccounting {
+        TransferAccounting {
+            identity,
+            totals: Mutex::new(TransferTotals::default()),
+            flush: None,
+        }
+    }
+
+    /// Passes the totals not yet taken to `sink` when the server stops.
+    pub fn on_flush(&mut self, sink: impl Fn(TransferTotals) + Send + Sync + 'static) -> &mut Self {
+        self.flush = Some(Box::new(sink));
+        self
+    }
+
+    pub(crate) fn has_flush(&self) -> bool {
+        self.flush.is_some()
+    }
+
+    pub(crate) fn flush(&self) {
+        if let Some(sink) = &self.flush {
+            sink(self.take());
+        }
+    }
+
+    /// The totals so far.
+    pub fn totals(&self) -> TransferTotals {
+        self.totals
+            .lock()
+            .map(|totals| totals.clone())
+            .unwrap_or_default()
+    }
+
+    /// The totals so far, starting new ones from zero.
+    pub fn take(&self) -> TransferTotals {
+        self.totals
+            .lock()
+            .map(|mut totals| mem::take(&mut *totals))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn client_of(&self, req: &Request) -> Option<String> {
+        self.identity.of(req)
+    }
+
+    pub(crate) fn record(
+        &self,
+        route: Option<String>,
+        client: Option<String>,
+        received: usize,
+        sent: usize,
+    ) {
+        let Ok(mut totals) = self.totals.lock() else {
+            return;
+        };
+
+        if let Some(route) = route {
+            *totals.routes.entry(route).or_default() += Transfer::request(received, sent);
+        }
+
+        if let Some(client) = client {
+            *totals.clients.entry(client).or_default() += Transfer::request(received, sent);
+        }
+    }
+}
+
+impl fmt::Debug for TransferAccounting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferAccounting")
+            .field("identity", &self.identity)
+            .field("totals", &self.totals)
+            .field("has_flush", &self.flush.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,545 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::http::Request;
+use crate::router::fallible::HandlerError;
+use crate::sha256;
+
+pub type Processor = Box<dyn Fn(&Upload) -> Result<(), HandlerError> + Send + Sync + 'static>;
+
+/// A file sent to an upload route.
+#[derive(Debug)]
+pub struct Upload<'a> {
+    pub file_name: Cow<'a, str>,
+    /// The declared media type, without parameters, lowercased.
+    pub content_type: Option<String>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Upload<'a> {
+    pub fn from_request(req: &'a Request, file_name: &'a str) -> Upload<'a> {
+        Upload {
+            file_name: Cow::Borrowed(file_name),
+            content_type: req.header_val("Content-Type").map(|val| {
+                let media_type: &str = val.split(';').next().unwrap_or_default();
+                media_type.trim().to_ascii_lowercase()
+            }),
+            body: &req.body,
+        }
+    }
+
+    /// The media type the body's leading bytes identify, if recognised.
+    pub fn sniffed_type(&self) -> Option<&'static str> {
+        sniff(self.body)
+    }
+
+    /// The text after the last `.` of the file name, lowercased.
+    pub fn extension(&self) -> Option<String> {
+        self.file_name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+    }
+}
+
+/// Picks the processor for an upload from its declared content type, falling
+/// back to its file extension.
+///
+/// Lookup order is an exact type (`image/png`), then a wildcard subtype
+/// (`image/*`), then the extension, then `*/*`. Uploads matching none of them
+/// are rejected as unsupported media.
+#[derive(Default)]
+pub struct UploadProcessors {
+    by_type: Vec<(String, Processor)>,
+    by_extension: Vec<(String, Processor)>,
+    sniffing: Option<SniffPolicy>,
+    scanners: Vec<Box<dyn UploadScanner>>,
+}
+
+/// Inspects an upload's content before it is processed or stored, e.g. by
+/// streaming it to a virus scanner.
+pub trait UploadScanner: Send + Sync {
+    fn scan(&self, upload: &Upload, body: &mut dyn Read) -> io::Result<ScanVerdict>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    /// The upload is refused with 422, giving the reason.
+    Rejected(String),
+}
+
+/// What to do with an upload whose content does not match the type it was
+/// declared as, by `Content-Type` or by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SniffPolicy {
+    /// Reject the upload with 415.
+    Reject,
+    /// Keep the upload under the extension of the sniffed type.
+    RenameExtension,
+}
+
+impl UploadProcessors {
+    pub fn new() -> UploadProcessors {
+        UploadProcessors::default()
+    }
+
+    /// Registers `processor` for a media type pattern: `type/subtype`,
+    /// `type/*` or `*/*`.
+    pub fn on_type(
+        &mut self,
+        pattern: &str,
+        processor: impl Fn(&Upload) -> Result<(), HandlerError> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let pattern: String = pattern.to_ascii_lowercase();
+        self.by_type.retain(|(p, _)| *p != pattern);
+        self.by_type.push((pattern, Box::new(processor)));
+        self
+    }
+
+    /// Registers `processor` for file names ending in `.extension`.
+    pub fn on_extension(
+        &mut self,
+        extension: &str,
+        processor: impl Fn(&Upload) -> Result<(), HandlerError> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let extension: String = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.by_extension.retain(|(e, _)| *e != extension);
+        self.by_extension.push((extension, Box::new(processor)));
+        self
+    }
+
+    /// Checks each upload's leading bytes against its declared type before
+    /// it reaches a processor.
+    pub fn sniff(&mut self, policy: SniffPolicy) -> &mut Self {
+        self.sniffing = Some(policy);
+        self
+    }
+
+    /// Adds a scanner run on every upload, after the sniffing guard and
+    /// before the processor. Scanners run in the order they were added.
+    pub fn scanner(&mut self, scanner: impl UploadScanner + 'static) -> &mut Self {
+        self.scanners.push(Box::new(scanner));
+        self
+    }
+
+    pub fn processor_for(&self, upload: &Upload) -> Option<&Processor> {
+        let by_type = |pattern: &str| {
+            self.by_type
+                .iter()
+                .find(|(p, _)| p == pattern)
+                .map(|(_, processor)| processor)
+        };
+
+        let content_type: Option<&str> = upload.content_type.as_deref();
+
+        content_type
+            .and_then(by_type)
+            .or_else(|| {
+                let (main_type, _) = content_type?.split_once('/')?;
+                by_type(&format!("{}/*", main_type))
+            })
+            .or_else(|| {
+                let extension: String = upload.extension()?;
+                self.by_extension
+                    .iter()
+                    .find(|(e, _)| *e == extension)
+                    .map(|(_, processor)| processor)
+            })
+            .or_else(|| by_type("*/*"))
+    }
+
+    /// Runs the sniffing guard, when enabled, then the scanners, then the
+    /// upload's processor.
+    /// The guard may rename `upload.file_name`.
+    pub fn process(&self, upload: &mut Upload) -> Result<(), HandlerError> {
+        if let Some(policy) = self.sniffing {
+            guard_sniffed(upload, policy)?;
+        }
+
+        for scanner in &self.scanners {
+            let verdict: ScanVerdict = scanner
+                .scan(upload, &mut Cursor::new(upload.body))
+                .map_err(|e| HandlerError::Internal(format!("upload scan failed: {}", e)))?;
+
+            if let ScanVerdict::Rejected(reason) = verdict {
+                return Err(HandlerError::Unprocessable(reason));
+            }
+        }
+
+        match self.processor_for(upload) {
+            Some(processor) => processor(upload),
+            None => Err(HandlerError::UnsupportedMediaType(
+                upload
+                    .content_type
+                    .clone()
+                    .unwrap_or_else(|| upload.file_name.to_string()),
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for UploadProcessors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let types: Vec<&String> = self.by_type.iter().map(|(p, _)| p).collect();
+        let extensions: Vec<&String> = self.by_extension.iter().map(|(e, _)| e).collect();
+
+        f.debug_struct("UploadProcessors")
+            .field("types", &types)
+            .field("extensions", &extensions)
+            .field("sniffing", &self.sniffing)
+            .field("scanners", &self.scanners.len())
+            .finish()
+    }
+}
+
+/// How far [`write_atomically`] goes to make a stored upload survive a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Durability {
+    /// Leave flushing to the OS. Readers still never see a partial file.
+    #[default]
+    None,
+    /// fsync the file before it is renamed into place.
+    File,
+    /// fsync the file and then its directory, so the rename itself is
+    /// persisted.
+    FileAndDirectory,
+}
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `bytes` to `dir/file_name` through a temporary file in `dir` that is
+/// renamed over the destination, so concurrent readers see either the old
+/// file or the complete new one.
+pub fn write_atomically(
+    dir: &Path,
+    file_name: &str,
+    bytes: &[u8],
+    durability: Durability,
+) -> io::Result<()> {
+    if !is_portable_file_name(file_name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` is not a portable file name", file_name),
+        ));
+    }
+
+    let temp_path = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written: io::Result<()> = (|| {
+        let mut file: File = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        file.write_all(bytes)?;
+
+        if durability != Durability::None {
+            file.sync_all()?;
+        }
+
+        fs::rename(&temp_path, dir.join(file_name))
+    })();
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    if durability == Durability::FileAndDirectory {
+        sync_directory(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Device names Windows reserves in every directory, whatever the extension.
+const RESERVED_DEVICE_NAMES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+/// Whether `name` names one plain file in a directory on both Unix and
+/// Windows: no separators or drive colons, no `.` or `..`, none of the
+/// characters Windows forbids, no trailing dot or space, and no reserved
+/// device name such as `NUL` or `com1.txt`.
+pub fn is_portable_file_name(name: &str) -> bool {
+    // A trailing dot also rules out `.` and `..`.
+    if name.is_empty() || name.ends_with(['.', ' ']) {
+        return false;
+    }
+
+    let forbidden = |c: char| {
+        c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+    };
+    if name.contains(forbidden) {
+        return false;
+    }
+
+    let stem: &str = name.split('.').next().unwrap_or(name).trim_end();
+    let is_numbered_port = |prefix: &str| {
+        let mut chars = stem.chars();
+        stem.get(..3)
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+            && chars
+                .nth(3)
+                .is_some_and(|c| c.is_ascii_digit() || matches!(c, '¹' | '²' | '³'))
+            && chars.next().is_none()
+    };
+
+    !(RESERVED_DEVICE_NAMES
+        .iter()
+        .any(|device| stem.eq_ignore_ascii_case(device))
+        || is_numbered_port("COM")
+        || is_numbered_port("LPT"))
+}
+
+#[cfg(unix)]
+fn sync_directory(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Stores uploads under the SHA-256 digest of their content, so identical
+/// uploads share one file. Each blob keeps a reference count in a `.refs`
+/// file next to it and is deleted once the last reference is released.
+#[derive(Debug)]
+pub struct ContentStore {
+    dir: PathBuf,
+    durability: Durability,
+    lock: Mutex<()>,
+}
+
+impl ContentStore {
+    pub fn new(dir: impl Into<PathBuf>, durability: Durability) -> ContentStore {
+        ContentStore {
+            dir: dir.into(),
+            durability,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Stores `bytes`, or takes another reference to an identical blob, and
+    /// returns its digest.
+    pub fn put(&self, bytes: &[u8]) -> io::Result<String> {
+        let digest: String = sha256::hex_digest(bytes);
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        fs::create_dir_all(&self.dir)?;
+
+        let refs: u64 = self.ref_count(&digest)?;
+        if refs == 0 {
+            write_atomically(&self.dir, &digest, bytes, self.durability)?;
+        }
+        self.set_ref_count(&digest, refs + 1)?;
+
+        Ok(digest)
+    }
+
+    pub fn get(&self, digest: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.blob_path(digest)?)
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.blob_path(digest).is_ok_and(|path| path.is_file())
+    }
+
+    /// Drops one reference to the blob, deleting it when none remain.
+    /// Returns whether the blob was deleted.
+    pub fn release(&self, digest: &str) -> io::Result<bool> {
+        let blob: PathBuf = self.blob_path(digest)?;
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        match self.ref_count(digest)? {
+            0 => Err(io::ErrorKind::NotFound.into()),
+            1 => {
+                fs::remove_file(blob)?;
+                fs::remove_file(self.refs_path(digest))?;
+                Ok(true)
+            }
+            refs => {
+                self.set_ref_count(digest, refs - 1)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn blob_path(&self, digest: &str) -> io::Result<PathBuf> {
+        let is_digest: bool = digest.len() == 64
+            && digest
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+
+        if !is_digest {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a SHA-256 hex digest",
+            ));
+        }
+
+        Ok(self.dir.join(digest))
+    }
+
+    fn refs_path(&self, digest: &str) -> PathBuf {
+        self.dir.join(format!("{}.refs", digest))
+    }
+
+    fn ref_count(&self, digest: &str) -> io::Result<u64> {
+        match fs::read_to_string(self.refs_path(digest)) {
+            Ok(refs) => refs
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "corrupt reference count")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_ref_count(&self, digest: &str, refs: u64) -> io::Result<()> {
+        write_atomically(
+            &self.dir,
+            &format!("{}.refs", digest),
+            refs.to_string().as_bytes(),
+            self.durability,
+        )
+    }
+}
+
+/// Media types recognised by [`sniff`], with the extension files of that type
+/// carry.
+const SNIFFABLE_TYPES: [(&str, &str); 9] = [
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/svg+xml", "svg"),
+    ("application/pdf", "pdf"),
+    ("application/zip", "zip"),
+    ("application/gzip", "gz"),
+    ("text/html", "html"),
+];
+
+/// Extensions mapped to the type their files are expected to contain.
+const EXTENSION_TYPES: [(&str, &str); 12] = [
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("txt", "text/plain"),
+];
+
+const HTML_PREFIXES: [&[u8]; 6] = [
+    b"<!doctype html",
+    b"<html",
+    b"<head",
+    b"<body",
+    b"<script",
+    b"<iframe",
+];
+
+/// Identifies a media type from the magic bytes at the start of `body`.
+/// Markup is recognised case-insensitively after leading whitespace.
+pub fn sniff(body: &[u8]) -> Option<&'static str> {
+    let signatures: [(&[u8], &'static str); 7] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1F\x8B", "application/gzip"),
+    ];
+
+    if body.len() >= 12 && body.starts_with(b"RIFF") && &body[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    let body: &[u8] = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+
+    if let Some((_, media_type)) = signatures.iter().find(|(magic, _)| body.starts_with(magic)) {
+        return Some(media_type);
+    }
+
+    let start: usize = body
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(body.len());
+    let markup: &[u8] = &body[start..];
+    let starts_with = |prefix: &[u8]| {
+        markup.len() >= prefix.len() && markup[..prefix.len()].eq_ignore_ascii_case(prefix)
+    };
+
+    if starts_with(b"<svg") || (starts_with(b"<?xml") && contains_svg_tag(markup)) {
+        return Some("image/svg+xml");
+    }
+
+    if HTML_PREFIXES.iter().any(|prefix| starts_with(prefix)) {
+        return Some("text/html");
+    }
+
+    None
+}
+
+fn contains_svg_tag(markup: &[u8]) -> bool {
+    let head: &[u8] = &markup[..markup.len().min(512)];
+    head.windows(4).any(|w| w.eq_ignore_ascii_case(b"<svg"))
+}
+
+fn guard_sniffed(upload: &mut Upload, policy: SniffPolicy) -> Result<(), HandlerError> {
+    let Some(sniffed) = upload.sniffed_type() else {
+        return Ok(());
+    };
+
+    let declared: Option<String> = match upload.content_type.as_deref() {
+        Some(content_type) if content_type != "application/octet-stream" => {
+            Some(String::from(content_type))
+        }
+        _ => upload.extension().and_then(|ext| {
+            EXTENSION_TYPES
+                .iter()
+                .find(|(e, _)| *e == ext)
+                .map(|(_, media_type)| String::from(*media_type))
+        }),
+    };
+
+    match declared {
+        Some(declared) if declared != sniffed => match policy {
+            SniffPolicy::Reject => Err(HandlerError::UnsupportedMediaType(format!(
+                "declared as {} but contains {}",
+                declared, sniffed
+            ))),
+            SniffPolicy::RenameExtension => {
+                let (_, extension) = SNIFFABLE_TYPES
+                    .iter()
+                    .find(|(media_type, _)| *media_type == sniffed)
+                    .ok_or_else(|| HandlerError::Internal(String::from("unknown sniffed type")))?;
+                let stem: &str = match upload.file_name.rsplit_once('.') {
+                    Some((stem, _)) => stem,
+                    None => &upload.file_name,
+                };
+
+                upload.file_name = Cow::Owned(format!("{}.{}", stem, extension));
+                upload.content_type = Some(String::from(sniffed));
+
+                Ok(())
+            }
+        },
+        _ => Ok(()),
+    }
+}
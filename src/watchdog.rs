@@ -0,0 +1,197 @@
+use std::process;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{error, warn};
+
+use crate::clock::Clock;
+use crate::subsystem::Periodic;
+
+/// Settings for the watchdog that checks the server keeps making progress.
+///
+/// A stall is an acceptor stuck on one connection, or a worker pool with
+/// queued jobs that completed none, for longer than `stall_after`. As a
+/// worker serves one connection for as long as it is kept alive,
+/// `stall_after` should be well above `Config::persist_connection_for`.
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    pub stall_after: Duration,
+    /// Log what each worker is doing when a stall is detected.
+    pub dump_threads: bool,
+    /// Exit the process with this code on a stall, so a supervisor can
+    /// restart it.
+    pub exit_on_stall: Option<i32>,
+}
+
+impl Watchdog {
+    pub fn new(stall_after: Duration) -> Watchdog {
+        Watchdog {
+            stall_after,
+            dump_threads: false,
+            exit_on_stall: None,
+        }
+    }
+}
+
+/// When a thread last started on a unit of work, if it is on one now.
+#[derive(Debug)]
+pub(crate) struct Activity {
+    origin: Instant,
+    /// Milliseconds since `origin` plus one, or 0 while idle.
+    busy_since: AtomicU64,
+}
+
+impl Activity {
+    pub(crate) fn new() -> Activity {
+        Activity {
+            origin: Instant::now(),
+            busy_since: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn begin(&self, now: Instant) {
+        let since: u64 = now.saturating_duration_since(self.origin).as_millis() as u64 + 1;
+        self.busy_since.store(since, Ordering::Relaxed);
+    }
+
+    pub(crate) fn end(&self) {
+        self.busy_since.store(0, Ordering::Relaxed);
+    }
+
+    fn busy_for(&self, now: Instant) -> Option<Duration> {
+        match self.busy_since.load(Ordering::Relaxed) {
+            0 => None,
+            since => Some(
+                now.saturating_duration_since(self.origin)
+                    .saturating_sub(Duration::from_millis(since - 1)),
+            ),
+        }
+    }
+}
+
+/// Progress counters a thread pool keeps for the watchdog.
+#[derive(Debug)]
+pub(crate) struct PoolHealth {
+    pub(crate) queued: AtomicUsize,
+    pub(crate) completed: AtomicU64,
+    pub(crate) workers: Vec<Activity>,
+}
+
+impl PoolHealth {
+    pub(crate) fn new(worker_count: usize) -> PoolHealth {
+        PoolHealth {
+            queued: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            workers: (0..worker_count).map(|_| Activity::new()).collect(),
+        }
+    }
+}
+
+/// The acceptors and pools a running server registers to be watched.
+#[derive(Debug, Default)]
+pub(crate) struct Monitor {
+    acceptors: Mutex<Vec<(usize, Arc<Activity>)>>,
+    pools: Mutex<Vec<(usize, Arc<PoolHealth>)>>,
+}
+
+impl Monitor {
+    pub(crate) fn watch_acceptor(&self, id: usize, activity: Arc<Activity>) {
+        if let Ok(mut acceptors) = self.acceptors.lock() {
+            acceptors.push((id, activity));
+        }
+    }
+
+    pub(crate) fn watch_pool(&self, id: usize, health: Arc<PoolHealth>) {
+        if let Ok(mut pools) = self.pools.lock() {
+            pools.push((id, health));
+        }
+    }
+
+    /// The periodic check run as the server's watchdog subsystem.
+    pub(crate) fn subsystem(
+        self: &Arc<Self>,
+        watchdog: Watchdog,
+        clock: Arc<dyn Clock>,
+    ) -> Periodic {
+        let monitor: Arc<Monitor> = Arc::clone(self);
+        let interval: Duration = (watchdog.stall_after / 4).max(Duration::from_millis(10));
+
+        // Per pool: the completed count when it was last seen progressing
+        // and since when it has had queued jobs without progress.
+        let mut progress: Vec<(u64, Option<Instant>)> = vec![];
+
+        Periodic::new("watchdog", interval, move || {
+            if monitor.check(&watchdog, clock.now(), &mut progress) {
+                if let Some(code) = watchdog.exit_on_stall {
+                    error!(code, "Exiting after a stall");
+                    process::exit(code);
+                }
+            }
+        })
+    }
+
+    /// Returns whether anything was found stalled.
+    fn check(
+        &self,
+        watchdog: &Watchdog,
+        now: Instant,
+        progress: &mut Vec<(u64, Option<Instant>)>,
+    ) -> bool {
+        let mut stalled: bool = false;
+
+        if let Ok(acceptors) = self.acceptors.lock() {
+            for (id, activity) in acceptors.iter() {
+                if let Some(busy_for) = activity.busy_for(now) {
+                    if busy_for > watchdog.stall_after {
+                        error!(acceptor = id, ?busy_for, "Accept loop is wedged");
+                        stalled = true;
+                    }
+                }
+            }
+        }
+
+        let Ok(pools) = self.pools.lock() else {
+            return stalled;
+        };
+
+        progress.resize(pools.len(), (0, None));
+
+        for ((id, health), (last_completed, stuck_since)) in pools.iter().zip(progress.iter_mut()) {
+            let completed: u64 = health.completed.load(Ordering::Relaxed);
+            let queued: usize = health.queued.load(Ordering::Relaxed);
+
+            if completed != *last_completed || queued == 0 {
+                *last_completed = completed;
+                *stuck_since = None;
+                continue;
+            }
+
+            let since: Instant = *stuck_since.get_or_insert(now);
+            let stalled_for: Duration = now.saturating_duration_since(since);
+            if stalled_for < watchdog.stall_after {
+                continue;
+            }
+
+            error!(
+                pool = id,
+                queued,
+                completed,
+                ?stalled_for,
+                "Thread pool is starved"
+            );
+            stalled = true;
+
+            if watchdog.dump_threads {
+                for (worker, activity) in health.workers.iter().enumerate() {
+                    match activity.busy_for(now) {
+                        Some(busy_for) => warn!(pool = id, worker, ?busy_for, "Worker busy"),
+                        None => warn!(pool = id, worker, "Worker idle"),
+                    }
+                }
+            }
+        }
+
+        stalled
+    }
+}
@@ -0,0 +1,77 @@
+//! Allocation budgets for the request hot path: parsing, routing and
+//! serializing a response, measured with the counting allocator.
+#![cfg(feature = "alloc-tracking")]
+
+use rsttp::alloc_tracking::{AllocBudget, AllocStats, CountingAllocator, measure};
+use rsttp::http::{ContentType, Request, Response, StatusCode};
+use rsttp::router::Router;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ECHO_REQUEST: &[u8] =
+    b"GET /echo/hello HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n";
+
+/// What one small request may cost end to end.
+const HOT_PATH_BUDGET: AllocBudget = AllocBudget {
+    max_allocations: 48,
+    max_bytes: 4 * 1024,
+};
+
+fn router() -> Router<()> {
+    let mut router: Router<()> = Router::new();
+    router
+        .get("/echo/:text", |req, params, _| {
+            Response::new(
+                req,
+                StatusCode::OK,
+                params.get("text").map(String::from),
+                ContentType::TextPlain,
+                req.protocol,
+            )
+        })
+        .unwrap();
+    router
+        .get("/bloat", |req, _, _| {
+            Response::from_bytes(
+                req,
+                StatusCode::OK,
+                Some(vec![b'x'; 64 * 1024]),
+                ContentType::ApplicationOctectStream,
+                req.protocol,
+            )
+        })
+        .unwrap();
+
+    router
+}
+
+fn serve(router: &Router<()>, raw: &[u8]) -> AllocStats {
+    let (bytes, stats) = measure(|| {
+        let req: Request = Request::new(raw).unwrap();
+        router.handle_request(req, &()).to_bytes()
+    });
+    assert!(bytes.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+    stats
+}
+
+#[test]
+fn echo_request_stays_within_budget() {
+    let router: Router<()> = router();
+    // The first request pays for lazily initialised state.
+    serve(&router, ECHO_REQUEST);
+
+    let stats: AllocStats = serve(&router, ECHO_REQUEST);
+    HOT_PATH_BUDGET.assert_within(&stats);
+}
+
+#[test]
+#[should_panic(expected = "allocation budget exceeded")]
+fn oversized_response_fails_budget() {
+    let router: Router<()> = router();
+    serve(&router, b"GET /bloat HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+    let stats: AllocStats = serve(&router, b"GET /bloat HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    HOT_PATH_BUDGET.assert_within(&stats);
+}
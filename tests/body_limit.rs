@@ -0,0 +1,120 @@
+//! Bodies over `Config::max_body_size` are refused with 413 before they
+//! are read, however they are framed.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rsttp::config::Config;
+use rsttp::http::{ContentType, Response, StatusCode};
+use rsttp::router::Router;
+use rsttp::rsttp_server::RsttpServer;
+
+fn start_server(config: impl FnOnce(&mut Config<()>)) -> u16 {
+    let port: u16 = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .port();
+
+    let mut router: Router<()> = Router::new();
+    router
+        .post("/echo", |req, _, _| {
+            Response::from_bytes(
+                req,
+                StatusCode::OK,
+                Some(req.body.clone()),
+                ContentType::ApplicationOctectStream,
+                req.protocol,
+            )
+        })
+        .unwrap();
+
+    let mut server_config: Config<()> = Config::new(i32::from(port), ());
+    config(&mut server_config);
+    let server: RsttpServer<()> = RsttpServer::new(server_config, router, 2);
+    thread::spawn(move || Arc::new(server).listen());
+
+    port
+}
+
+/// Sends `request` and returns the status line of the response.
+fn status_of(port: u16, request: &[u8]) -> String {
+    let mut stream: Option<TcpStream> = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(("127.0.0.1", port)) {
+            stream = Some(connected);
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mut stream: TcpStream = stream.expect("server did not start");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(request).unwrap();
+
+    let mut response: Vec<u8> = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    let response: String = String::from_utf8_lossy(&response).into_owned();
+
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn default_limit_refuses_oversized_content_length() {
+    let port: u16 = start_server(|_| ());
+
+    let status: String = status_of(
+        port,
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 9000000\r\n\r\n",
+    );
+    assert_eq!(status, "HTTP/1.1 413 Content Too Large");
+}
+
+#[test]
+fn refusal_is_sent_in_the_request_version() {
+    let port: u16 = start_server(|_| ());
+
+    let status: String = status_of(
+        port,
+        b"POST /echo HTTP/1.0\r\nContent-Length: 9000000\r\n\r\n",
+    );
+    assert_eq!(status, "HTTP/1.0 413 Content Too Large");
+}
+
+#[test]
+fn default_limit_refuses_oversized_chunk() {
+    let port: u16 = start_server(|_| ());
+
+    // 0x900000 bytes is over the 8 MiB default.
+    let status: String = status_of(
+        port,
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n900000\r\n",
+    );
+    assert_eq!(status, "HTTP/1.1 413 Content Too Large");
+}
+
+#[test]
+fn chunks_past_the_limit_together_are_refused() {
+    let port: u16 = start_server(|config| config.max_body_size = Some(8));
+
+    let status: String = status_of(
+        port,
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n",
+    );
+    assert_eq!(status, "HTTP/1.1 413 Content Too Large");
+}
+
+#[test]
+fn body_within_the_limit_is_served() {
+    let port: u16 = start_server(|config| config.max_body_size = Some(8));
+
+    let status: String = status_of(
+        port,
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    assert_eq!(status, "HTTP/1.1 200 OK");
+}
@@ -0,0 +1,97 @@
+//! Event streams must reach the client event by event, not when the
+//! handler returns.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use rsttp::config::Config;
+use rsttp::http::Response;
+use rsttp::router::Router;
+use rsttp::rsttp_server::RsttpServer;
+
+/// Holds the stream open after its first event until the client has read it.
+static FIRST_EVENT_READ: Barrier = Barrier::new(2);
+
+fn start_server() -> u16 {
+    let port: u16 = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .port();
+
+    let mut router: Router<()> = Router::new();
+    router
+        .get("/events", |_, _, _| {
+            Response::event_stream(|mut events| {
+                events.send(Some("greeting"), "first").unwrap();
+                FIRST_EVENT_READ.wait();
+                let _ = events.send(None, "second");
+            })
+        })
+        .unwrap();
+
+    let server: RsttpServer<()> = RsttpServer::new(Config::new(i32::from(port), ()), router, 2);
+    thread::spawn(move || Arc::new(server).listen());
+
+    port
+}
+
+fn connect(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    panic!("server did not start on port {}", port);
+}
+
+#[test]
+fn first_event_arrives_before_stream_ends() {
+    let port: u16 = start_server();
+    let mut stream: TcpStream = connect(port);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+
+    let mut reader: BufReader<TcpStream> = BufReader::new(stream);
+    let mut head: Vec<String> = vec![];
+    loop {
+        let mut line: String = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        head.push(line);
+    }
+    assert_eq!(head[0], "HTTP/1.1 200 OK\r\n");
+    assert!(head.contains(&String::from("Content-Type: text/event-stream\r\n")));
+    assert!(!head.iter().any(|line| line.starts_with("Content-Length")));
+
+    // The handler is still blocked, so this event can only have arrived
+    // if it was flushed on its own.
+    let mut event: Vec<String> = vec![];
+    loop {
+        let mut line: String = String::new();
+        reader
+            .read_line(&mut line)
+            .expect("first event was not flushed before the stream ended");
+        if line == "\n" {
+            break;
+        }
+        event.push(line);
+    }
+    assert_eq!(event, vec!["event: greeting\n", "data: first\n"]);
+
+    FIRST_EVENT_READ.wait();
+
+    let mut rest: String = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "data: second\n\n");
+}
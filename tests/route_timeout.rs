@@ -0,0 +1,103 @@
+//! Handlers of routes with a timeout run on a bounded pool: overrunning
+//! handlers keep their threads, and once all are taken further requests
+//! are refused instead of starting more.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use rsttp::config::Config;
+use rsttp::http::Response;
+use rsttp::router::Router;
+use rsttp::rsttp_server::RsttpServer;
+
+const WORKERS: usize = 2;
+
+/// Holds the slow handlers until the test has seen them overrun.
+static RELEASE: Barrier = Barrier::new(WORKERS + 1);
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+fn start_server() -> u16 {
+    let port: u16 = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .port();
+
+    let mut router: Router<()> = Router::new();
+    router
+        .get("/slow", |_, _, _| {
+            STARTED.fetch_add(1, Ordering::SeqCst);
+            RELEASE.wait();
+            Response::success()
+        })
+        .unwrap()
+        .with_timeout(Duration::from_millis(100));
+    router
+        .get("/fast", |_, _, _| Response::success())
+        .unwrap()
+        .with_timeout(Duration::from_secs(5));
+
+    let server: RsttpServer<()> =
+        RsttpServer::new(Config::new(i32::from(port), ()), router, WORKERS);
+    thread::spawn(move || Arc::new(server).listen());
+
+    port
+}
+
+/// Requests `path` on a fresh connection and returns the status line.
+fn status_of(port: u16, path: &str) -> String {
+    let mut stream: Option<TcpStream> = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(("127.0.0.1", port)) {
+            stream = Some(connected);
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mut stream: TcpStream = stream.expect("server did not start");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    )
+    .unwrap();
+
+    let mut response: Vec<u8> = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    let response: String = String::from_utf8_lossy(&response).into_owned();
+
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn overrunning_handlers_are_bounded() {
+    let port: u16 = start_server();
+
+    for _ in 0..WORKERS {
+        assert_eq!(status_of(port, "/slow"), "HTTP/1.1 503 Service Unavailable");
+    }
+
+    // Every handler thread is held by a handler that overran, so these are
+    // refused without running.
+    assert_eq!(status_of(port, "/slow"), "HTTP/1.1 503 Service Unavailable");
+    assert_eq!(status_of(port, "/fast"), "HTTP/1.1 503 Service Unavailable");
+    assert_eq!(STARTED.load(Ordering::SeqCst), WORKERS);
+
+    RELEASE.wait();
+
+    let mut status: String = String::new();
+    for _ in 0..50 {
+        status = status_of(port, "/fast");
+        if status == "HTTP/1.1 200 OK" {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(status, "HTTP/1.1 200 OK");
+}